@@ -1,4 +1,4 @@
-use anyhow::Result;
+use crate::error::Result;
 use rppal::gpio::{Gpio, OutputPin};
 use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
 use std::thread::sleep;
@@ -38,6 +38,7 @@ const CS_WAIT: u64 = 3;
 const RD_WAIT: u64 = 4;
 const WR_WAIT_BEFORE: u64 = 1;
 const WR_WAIT_AFTER: u64 = 5;
+const RST_WAIT: u64 = 1000;
 
 const CMD_WRITE: u8 = 0x40;
 const CMD_READ: u8 = 0x41;
@@ -51,7 +52,7 @@ enum DataDir {
 }
 
 pub struct CubicStyleBoard {
-    gpio: Gpio,
+    _gpio: Gpio,
     spi: Spi,
 
     rd: OutputPin,
@@ -67,31 +68,31 @@ impl CubicStyleBoard {
     pub fn new() -> Result<Self> {
         let gpio = Gpio::new()?;
 
-        let rd = (&gpio).get(Pin::Rd as u8)?.into_output();
-        let wr = (&gpio).get(Pin::Wr as u8)?.into_output();
-        let cs = (&gpio).get(Pin::Cs as u8)?.into_output();
-        let rst = (&gpio).get(Pin::Rst as u8)?.into_output();
+        let rd = gpio.get(Pin::Rd as u8)?.into_output();
+        let wr = gpio.get(Pin::Wr as u8)?.into_output();
+        let cs = gpio.get(Pin::Cs as u8)?.into_output();
+        let rst = gpio.get(Pin::Rst as u8)?.into_output();
         let addr = [
-            (&gpio).get(Pin::Addr0 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr1 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr2 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr3 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr4 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr5 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr6 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr7 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr8 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr9 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr10 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr11 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr12 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr13 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr14 as u8)?.into_output(),
-            (&gpio).get(Pin::Addr15 as u8)?.into_output(),
+            gpio.get(Pin::Addr0 as u8)?.into_output(),
+            gpio.get(Pin::Addr1 as u8)?.into_output(),
+            gpio.get(Pin::Addr2 as u8)?.into_output(),
+            gpio.get(Pin::Addr3 as u8)?.into_output(),
+            gpio.get(Pin::Addr4 as u8)?.into_output(),
+            gpio.get(Pin::Addr5 as u8)?.into_output(),
+            gpio.get(Pin::Addr6 as u8)?.into_output(),
+            gpio.get(Pin::Addr7 as u8)?.into_output(),
+            gpio.get(Pin::Addr8 as u8)?.into_output(),
+            gpio.get(Pin::Addr9 as u8)?.into_output(),
+            gpio.get(Pin::Addr10 as u8)?.into_output(),
+            gpio.get(Pin::Addr11 as u8)?.into_output(),
+            gpio.get(Pin::Addr12 as u8)?.into_output(),
+            gpio.get(Pin::Addr13 as u8)?.into_output(),
+            gpio.get(Pin::Addr14 as u8)?.into_output(),
+            gpio.get(Pin::Addr15 as u8)?.into_output(),
         ];
 
         Ok(Self {
-            gpio,
+            _gpio: gpio,
             spi: Spi::new(Bus::Spi0, SlaveSelect::Ss1, 4000000, Mode::Mode0)?,
             rd,
             wr,
@@ -117,6 +118,24 @@ impl CubicStyleBoard {
         Ok(())
     }
 
+    // 制御線をすべてHighに戻し、DataをINPUTにしてバスを解放する
+    pub fn idle(&mut self) -> Result<()> {
+        self.rd.set_high();
+        self.wr.set_high();
+        self.rst.set_high();
+        self.cs.set_high();
+
+        self.mcp_into_input()
+    }
+
+    pub fn reset(&mut self) {
+        self.rst.set_low();
+        sleep(Duration::from_micros(RST_WAIT));
+
+        self.rst.set_high();
+        sleep(Duration::from_micros(RST_WAIT));
+    }
+
     pub fn set_addr(&mut self, addr: u16) {
         for i in 0..16 {
             let pin = &mut self.addr[i];
@@ -248,9 +267,6 @@ impl CubicStyleBoard {
 
 impl Drop for CubicStyleBoard {
     fn drop(&mut self) {
-        self.rd.set_high();
-        self.wr.set_high();
-        self.cs.set_high();
-        self.rst.set_high();
+        let _ = self.idle();
     }
 }
@@ -0,0 +1,249 @@
+use crate::error::{Error, Result};
+use crate::hash::Hashes;
+use std::fs;
+use std::path::Path;
+
+// No-IntroやRedumpが配布しているLogiqx XML形式のDATファイル
+
+// No-Introの名前に含まれる地域名
+const REGIONS: [&str; 20] = [
+    "World",
+    "Japan",
+    "USA",
+    "Europe",
+    "Asia",
+    "Australia",
+    "Brazil",
+    "Canada",
+    "China",
+    "France",
+    "Germany",
+    "Hong Kong",
+    "Italy",
+    "Korea",
+    "Netherlands",
+    "Spain",
+    "Sweden",
+    "Taiwan",
+    "UK",
+    "Unknown",
+];
+
+#[derive(Clone, Debug, Default)]
+pub struct DatEntry {
+    pub name: String,
+    pub rom_name: String,
+    pub size: usize,
+    pub crc32: Option<u32>,
+    pub md5: Option<String>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+    // status="baddump"
+    pub bad: bool,
+}
+
+impl DatEntry {
+    // "Tetris (World) (Rev 1)" -> "World"
+    pub fn region(&self) -> Option<String> {
+        self.tags()
+            .find(|tag| tag.split(", ").all(|region| REGIONS.contains(&region)))
+            .map(|tag| tag.to_string())
+    }
+
+    // "Tetris (World) (Rev 1)" -> "Rev 1", "Pokemon - Crystal Version (USA, Europe) (v1.1)" -> "v1.1"
+    pub fn revision(&self) -> Option<String> {
+        self.tags()
+            .find(|tag| {
+                tag.starts_with("Rev ")
+                    || (tag.starts_with('v') && tag[1..].starts_with(|c: char| c.is_ascii_digit()))
+            })
+            .map(|tag| tag.to_string())
+    }
+
+    fn tags(&self) -> impl Iterator<Item = &str> {
+        self.name
+            .split('(')
+            .skip(1)
+            .filter_map(|tag| tag.split(')').next())
+    }
+
+    // DATに記載されているハッシュがすべて一致するか
+    fn matches(&self, hashes: &Hashes) -> bool {
+        let checks = [
+            self.crc32.map(|crc32| crc32 == hashes.crc32),
+            self.md5
+                .as_ref()
+                .map(|md5| md5.eq_ignore_ascii_case(&hashes.md5_hex())),
+            self.sha1
+                .as_ref()
+                .map(|sha1| sha1.eq_ignore_ascii_case(&hashes.sha1_hex())),
+            self.sha256
+                .as_ref()
+                .map(|sha256| sha256.eq_ignore_ascii_case(&hashes.sha256_hex())),
+        ];
+
+        self.size == hashes.size
+            && checks.iter().any(Option::is_some)
+            && checks.iter().all(|check| check.unwrap_or(true))
+    }
+}
+
+#[derive(Debug)]
+pub enum Lookup<'a> {
+    Verified(&'a DatEntry),
+    BadDump(&'a DatEntry),
+    Unknown,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Dat {
+    pub entries: Vec<DatEntry>,
+}
+
+impl Dat {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(xml: &str) -> Result<Self> {
+        // Logiqx形式のDATは先頭にDOCTYPEを含む
+        let options = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        };
+
+        let doc = roxmltree::Document::parse_with_options(xml, options)
+            .map_err(|e| Error::InvalidDat(e.to_string()))?;
+
+        let mut entries = Vec::new();
+
+        // MAME形式のDATは<game>の代わりに<machine>を使う
+        for game in doc
+            .root_element()
+            .children()
+            .filter(|n| n.has_tag_name("game") || n.has_tag_name("machine"))
+        {
+            let name = game
+                .attribute("name")
+                .ok_or_else(|| Error::InvalidDat("game without name".to_string()))?;
+
+            for rom in game.children().filter(|n| n.has_tag_name("rom")) {
+                entries.push(DatEntry {
+                    name: name.to_string(),
+                    rom_name: rom.attribute("name").unwrap_or(name).to_string(),
+                    size: rom
+                        .attribute("size")
+                        .and_then(|size| size.parse().ok())
+                        .unwrap_or_default(),
+                    crc32: rom
+                        .attribute("crc")
+                        .and_then(|crc| u32::from_str_radix(crc, 16).ok()),
+                    md5: rom.attribute("md5").map(str::to_string),
+                    sha1: rom.attribute("sha1").map(str::to_string),
+                    sha256: rom.attribute("sha256").map(str::to_string),
+                    bad: rom.attribute("status") == Some("baddump"),
+                });
+            }
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn lookup(&self, hashes: &Hashes) -> Lookup<'_> {
+        match self.entries.iter().find(|entry| entry.matches(hashes)) {
+            Some(entry) if entry.bad => Lookup::BadDump(entry),
+            Some(entry) => Lookup::Verified(entry),
+            None => Lookup::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dat() -> Dat {
+        let good = Hashes::of(b"abc");
+        let bad = Hashes::of(b"abcd");
+
+        Dat::parse(&format!(
+            r#"<?xml version="1.0"?>
+<!DOCTYPE datafile PUBLIC "-//Logiqx//DTD ROM Management Datafile//EN" "http://www.logiqx.com/dtds/logiqx.dtd">
+<datafile>
+    <header>
+        <name>Nintendo - Game Boy</name>
+    </header>
+    <game name="Tetris (World) (Rev 1)">
+        <description>Tetris (World) (Rev 1)</description>
+        <rom name="Tetris (World) (Rev 1).gb" size="3" crc="{}" md5="{}" sha1="{}"/>
+    </game>
+    <machine name="Pokemon - Crystal Version (USA, Europe) (v1.1)">
+        <rom name="Pokemon - Crystal Version (USA, Europe) (v1.1).gbc" size="4" crc="{}" status="baddump"/>
+    </machine>
+</datafile>
+"#,
+            good.crc32_hex(),
+            good.md5_hex().to_lowercase(),
+            good.sha1_hex().to_lowercase(),
+            bad.crc32_hex(),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_reads_games_and_machines() {
+        let dat = dat();
+
+        assert_eq!(dat.entries.len(), 2);
+        assert_eq!(dat.entries[0].name, "Tetris (World) (Rev 1)");
+        assert_eq!(dat.entries[0].rom_name, "Tetris (World) (Rev 1).gb");
+        assert_eq!(dat.entries[0].size, 3);
+        assert!(!dat.entries[0].bad);
+        assert_eq!(
+            dat.entries[1].rom_name,
+            "Pokemon - Crystal Version (USA, Europe) (v1.1).gbc"
+        );
+        assert!(dat.entries[1].bad);
+    }
+
+    #[test]
+    fn lookup_matches_hashes() {
+        let dat = dat();
+
+        assert!(matches!(
+            dat.lookup(&Hashes::of(b"abc")),
+            Lookup::Verified(entry) if entry.name == "Tetris (World) (Rev 1)"
+        ));
+        assert!(matches!(
+            dat.lookup(&Hashes::of(b"abcd")),
+            Lookup::BadDump(_)
+        ));
+        assert!(matches!(dat.lookup(&Hashes::of(b"xyz")), Lookup::Unknown));
+    }
+
+    #[test]
+    fn lookup_requires_every_listed_hash() {
+        let mut dat = dat();
+        dat.entries[0].md5 = Some("00000000000000000000000000000000".to_string());
+
+        assert!(matches!(dat.lookup(&Hashes::of(b"abc")), Lookup::Unknown));
+    }
+
+    #[test]
+    fn region_and_revision_come_from_tags() {
+        let dat = dat();
+
+        assert_eq!(dat.entries[0].region(), Some("World".to_string()));
+        assert_eq!(dat.entries[0].revision(), Some("Rev 1".to_string()));
+        assert_eq!(dat.entries[1].region(), Some("USA, Europe".to_string()));
+        assert_eq!(dat.entries[1].revision(), Some("v1.1".to_string()));
+    }
+
+    #[test]
+    fn invalid_xml_is_rejected() {
+        assert!(matches!(
+            Dat::parse("<datafile><game>"),
+            Err(Error::InvalidDat(_))
+        ));
+    }
+}
@@ -0,0 +1,475 @@
+use crate::error::{Error, Result};
+use crate::mbc::MbcReader;
+use crate::rom::global_checksum;
+use crate::utils::bytes_to_hex;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub const BANK_SIZE: usize = 0x4000;
+
+const CHUNK_SIZE: usize = 0x0100;
+
+// 読み出しの進捗。コールバックがエラーを返すと読み出しを中断する
+#[derive(Clone, Debug)]
+pub struct Progress {
+    pub done: usize,
+    pub total: usize,
+    pub status: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VoteReport {
+    // 多数決で1回目の読み出し結果から値を変えたバイト数
+    pub corrected_bytes: usize,
+    // 読み出すたびに内容が変わったバンク
+    pub unstable_banks: Vec<usize>,
+}
+
+// ROM全体をpasses回読み出し、バンクごとにハッシュを比較する
+// 一致しなかったバンクは追加でpasses回読み直し、バイトごとの多数決で内容を決める
+pub fn read_multi_pass(
+    reader: &mut dyn MbcReader,
+    passes: usize,
+    mut on_progress: impl FnMut(&Progress) -> Result<()>,
+) -> Result<(Vec<u8>, VoteReport)> {
+    let passes = passes.max(1);
+    let size = reader.size();
+    let banks = size.div_ceil(BANK_SIZE);
+
+    let mut progress = Progress {
+        done: 0,
+        total: size * passes,
+        status: String::new(),
+    };
+
+    let mut reads = Vec::with_capacity(passes);
+
+    for _ in 0..passes {
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut data = vec![0; size];
+        read_range(reader, &mut data, &mut progress, &mut on_progress)?;
+
+        reads.push(data);
+    }
+
+    let unstable = (0..banks)
+        .filter(|&bank| {
+            let range = bank_range(bank, size);
+            let first = hash_bank(&reads[0][range.clone()]);
+
+            reads[1..]
+                .iter()
+                .any(|data| hash_bank(&data[range.clone()]) != first)
+        })
+        .collect::<Vec<_>>();
+
+    progress.total += unstable.len() * BANK_SIZE * passes;
+
+    let mut output = reads[0].clone();
+    let mut report = VoteReport {
+        corrected_bytes: 0,
+        unstable_banks: unstable.clone(),
+    };
+
+    for bank in unstable {
+        let range = bank_range(bank, size);
+
+        let mut samples = reads
+            .iter()
+            .map(|data| data[range.clone()].to_vec())
+            .collect::<Vec<_>>();
+
+        for _ in 0..passes {
+            samples.push(read_bank(reader, bank, &mut progress, &mut on_progress)?);
+        }
+
+        report.corrected_bytes += vote(&samples, &mut output[range]);
+    }
+
+    Ok((output, report))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RetryReport {
+    // グローバルチェックサムが一致したかどうか
+    pub matched: bool,
+    // 全バンクを読み直した回数
+    pub rounds: usize,
+    // 読み直した結果が一致しなかったバンク
+    pub unstable_banks: Vec<usize>,
+}
+
+// グローバルチェックサムが一致するまで、最大retries回全バンクを読み直す
+// バンクごとにこれまでの読み出し結果を保持し、多数決で内容を決め直す
+// 最初の読み出し結果と読み直した結果の1対1では多数決にならないため、1回目は各バンクを2回読み直す
+pub fn retry_until_checksum(
+    reader: &mut dyn MbcReader,
+    rom: &mut [u8],
+    expected: u16,
+    retries: usize,
+    mut on_progress: impl FnMut(&Progress) -> Result<()>,
+) -> Result<RetryReport> {
+    let size = rom.len().min(reader.size());
+    let banks = size.div_ceil(BANK_SIZE);
+
+    let mut progress = Progress {
+        done: 0,
+        total: retry_size(size, retries),
+        status: String::new(),
+    };
+
+    let mut samples = (0..banks)
+        .map(|bank| vec![rom[bank_range(bank, size)].to_vec()])
+        .collect::<Vec<_>>();
+
+    let mut report = RetryReport {
+        matched: global_checksum(rom) == expected,
+        ..Default::default()
+    };
+    let mut unstable = BTreeSet::new();
+
+    while !report.matched && report.rounds < retries {
+        for (bank, samples) in samples.iter_mut().enumerate() {
+            let reads = if samples.len() == 1 { 2 } else { 1 };
+
+            for _ in 0..reads {
+                let data = read_bank(reader, bank, &mut progress, &mut on_progress)?;
+
+                if data != samples[0] {
+                    unstable.insert(bank);
+                }
+
+                samples.push(data);
+            }
+
+            vote(samples, &mut rom[bank_range(bank, size)]);
+        }
+
+        report.rounds += 1;
+        report.matched = global_checksum(rom) == expected;
+    }
+
+    report.unstable_banks = unstable.into_iter().collect();
+
+    Ok(report)
+}
+
+// retry_until_checksumで最大何バイト読み直すか
+pub fn retry_size(size: usize, retries: usize) -> usize {
+    if retries == 0 {
+        0
+    } else {
+        size * (retries + 1)
+    }
+}
+
+// 指定したバンクを読み直す
+pub fn read_bank(
+    reader: &mut dyn MbcReader,
+    bank: usize,
+    progress: &mut Progress,
+    on_progress: &mut impl FnMut(&Progress) -> Result<()>,
+) -> Result<Vec<u8>> {
+    let range = bank_range(bank, reader.size());
+
+    reader.seek(SeekFrom::Start(range.start as u64))?;
+
+    let mut data = vec![0; range.len()];
+    read_range(reader, &mut data, progress, on_progress)?;
+
+    Ok(data)
+}
+
+// 複数の読み出し結果からバイトごとに最も多い値を選び、最初の読み出し結果から値を変えたバイト数を返す
+pub fn vote(samples: &[Vec<u8>], output: &mut [u8]) -> usize {
+    let mut corrected = 0;
+
+    for (i, out) in output.iter_mut().enumerate() {
+        let mut counts = [0_usize; 0x100];
+
+        for sample in samples {
+            counts[sample[i] as usize] += 1;
+        }
+
+        // 同数の場合は先に読んだ値を優先する
+        let mut best = samples[0][i];
+
+        for sample in samples {
+            if counts[sample[i] as usize] > counts[best as usize] {
+                best = sample[i];
+            }
+        }
+
+        if best != samples[0][i] {
+            corrected += 1;
+        }
+
+        *out = best;
+    }
+
+    corrected
+}
+
+pub fn bank_range(bank: usize, size: usize) -> std::ops::Range<usize> {
+    bank * BANK_SIZE..((bank + 1) * BANK_SIZE).min(size)
+}
+
+fn hash_bank(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn read_range(
+    reader: &mut dyn MbcReader,
+    data: &mut [u8],
+    progress: &mut Progress,
+    on_progress: &mut impl FnMut(&Progress) -> Result<()>,
+) -> Result<()> {
+    for chunk in data.chunks_mut(CHUNK_SIZE) {
+        reader.read_exact(chunk)?;
+
+        progress.done += chunk.len();
+        progress.status = reader.status();
+
+        on_progress(progress)?;
+    }
+
+    Ok(())
+}
+
+// 中断した読み出しを再開するため、読み終えたバンクのCRC32を記録するファイル
+// header <0x0000-0x014Fの16進数>
+// size <ROMサイズ>
+// bank <バンク番号> <CRC32>
+pub struct ResumeLog {
+    path: PathBuf,
+    header: Vec<u8>,
+    size: usize,
+    banks: Vec<u32>,
+}
+
+impl ResumeLog {
+    pub fn create<P: AsRef<Path>>(path: P, header: &[u8], size: usize) -> Result<Self> {
+        let log = Self {
+            path: path.as_ref().to_path_buf(),
+            header: header.to_vec(),
+            size,
+            banks: Vec::new(),
+        };
+
+        log.save()?;
+
+        Ok(log)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let text = fs::read_to_string(&path)?;
+
+        let mut header = None;
+        let mut size = None;
+        let mut banks = Vec::new();
+
+        for line in text.lines() {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+
+            match fields.as_slice() {
+                ["header", hex] => header = Some(parse_hex(hex)?),
+                ["size", n] => size = Some(n.parse().map_err(|_| invalid(line))?),
+                ["bank", bank, crc] => {
+                    if bank.parse::<usize>() != Ok(banks.len()) {
+                        return Err(invalid(line));
+                    }
+
+                    banks.push(u32::from_str_radix(crc, 16).map_err(|_| invalid(line))?);
+                }
+                [] => {}
+                _ => return Err(invalid(line)),
+            }
+        }
+
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            header: header.ok_or_else(|| invalid("missing header"))?,
+            size: size.ok_or_else(|| invalid("missing size"))?,
+            banks,
+        })
+    }
+
+    pub fn matches(&self, header: &[u8], size: usize) -> bool {
+        self.header == header && self.size == size
+    }
+
+    pub fn completed_banks(&self) -> usize {
+        self.banks.len()
+    }
+
+    // 出力ファイルの内容と記録したCRC32を比較し、一致した先頭のバンク数を返す
+    pub fn verify(&mut self, rom: &[u8]) -> Result<usize> {
+        let verified = self
+            .banks
+            .iter()
+            .enumerate()
+            .take_while(|&(bank, &crc)| {
+                let range = bank_range(bank, self.size);
+
+                range.end <= rom.len() && crc32fast::hash(&rom[range]) == crc
+            })
+            .count();
+
+        if verified != self.banks.len() {
+            self.banks.truncate(verified);
+            self.save()?;
+        }
+
+        Ok(verified)
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Result<()> {
+        let crc = crc32fast::hash(data);
+
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        writeln!(file, "bank {} {:08X}", self.banks.len(), crc)?;
+
+        self.banks.push(crc);
+
+        Ok(())
+    }
+
+    pub fn remove(self) -> Result<()> {
+        fs::remove_file(&self.path)?;
+
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut text = format!(
+            "header {}\nsize {}\n",
+            bytes_to_hex(&self.header),
+            self.size
+        );
+
+        for (bank, crc) in self.banks.iter().enumerate() {
+            text += &format!("bank {} {:08X}\n", bank, crc);
+        }
+
+        fs::write(&self.path, text)?;
+
+        Ok(())
+    }
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return Err(invalid(hex));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid(hex)))
+        .collect()
+}
+
+fn invalid(line: &str) -> Error {
+    Error::InvalidResumeLog(line.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::testing::MemoryReader;
+
+    // 2バンク分のROMを作り、グローバルチェックサムを書き込む
+    fn rom() -> Vec<u8> {
+        let mut rom = (0..BANK_SIZE * 2)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+
+        let checksum = global_checksum(&rom);
+        rom[0x014E..0x0150].copy_from_slice(&checksum.to_be_bytes());
+
+        rom
+    }
+
+    #[test]
+    fn vote_picks_majority() {
+        let samples = vec![vec![1, 2, 3], vec![1, 9, 3], vec![1, 9, 4]];
+        let mut output = [0; 3];
+
+        assert_eq!(vote(&samples, &mut output), 1);
+        assert_eq!(output, [1, 9, 3]);
+    }
+
+    #[test]
+    fn vote_does_not_count_bad_rereads() {
+        let samples = vec![vec![1, 2], vec![1, 2], vec![1, 9]];
+        let mut output = [0; 2];
+
+        assert_eq!(vote(&samples, &mut output), 0);
+        assert_eq!(output, [1, 2]);
+    }
+
+    #[test]
+    fn vote_prefers_first_sample_on_tie() {
+        let samples = vec![vec![1, 2], vec![1, 9]];
+        let mut output = [0; 2];
+
+        assert_eq!(vote(&samples, &mut output), 0);
+        assert_eq!(output, [1, 2]);
+    }
+
+    #[test]
+    fn retry_fixes_bad_byte_in_first_round() {
+        let expected = rom();
+        let checksum = global_checksum(&expected);
+
+        let mut dumped = expected.clone();
+        dumped[BANK_SIZE + 0x10] ^= 0xFF;
+
+        let mut reader = MemoryReader::new(expected.clone(), &[]);
+        let report =
+            retry_until_checksum(&mut reader, &mut dumped, checksum, 1, |_| Ok(())).unwrap();
+
+        assert!(report.matched);
+        assert_eq!(report.rounds, 1);
+        assert_eq!(report.unstable_banks, vec![1]);
+        assert_eq!(dumped, expected);
+    }
+
+    #[test]
+    fn retry_outvotes_flaky_reread() {
+        let expected = rom();
+        let checksum = global_checksum(&expected);
+
+        let mut dumped = expected.clone();
+        dumped[0x0200] ^= 0xFF;
+
+        // 読み直しでも1回目だけ同じバイトが壊れる
+        let mut reader = MemoryReader::new(expected.clone(), &[0x0200]);
+        let report =
+            retry_until_checksum(&mut reader, &mut dumped, checksum, 3, |_| Ok(())).unwrap();
+
+        assert!(report.matched);
+        assert_eq!(report.unstable_banks, vec![0]);
+        assert_eq!(dumped, expected);
+    }
+
+    #[test]
+    fn retry_skips_matching_rom() {
+        let mut dumped = rom();
+        let checksum = global_checksum(&dumped);
+
+        let mut reader = MemoryReader::new(dumped.clone(), &[]);
+        let report =
+            retry_until_checksum(&mut reader, &mut dumped, checksum, 3, |_| Ok(())).unwrap();
+
+        assert!(report.matched);
+        assert_eq!(report.rounds, 0);
+    }
+}
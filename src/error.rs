@@ -0,0 +1,79 @@
+use crate::rom::MbcType;
+use std::io;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("GPIO error: {0}")]
+    Gpio(#[from] rppal::gpio::Error),
+
+    #[error("SPI bus error: {0}")]
+    Bus(#[from] rppal::spi::Error),
+
+    #[error("I/O error: {0}")]
+    Io(io::Error),
+
+    #[error("failed to parse the {field}: {source}")]
+    HeaderField {
+        field: &'static str,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("invalid header checksum expected: {expected:#04X}, actual: {actual:#04X}")]
+    HeaderChecksum { expected: u8, actual: u8 },
+
+    #[error("unsupported mapper: {0:#04X}")]
+    UnsupportedMapper(u8),
+
+    #[error("unknown mapper: {0}")]
+    UnknownMapperName(String),
+
+    #[error("unsupported SRAM backup: {0:?}")]
+    UnsupportedSram(MbcType),
+
+    #[error("unknown ROM size {0:#04X}, specify the ROM size explicitly")]
+    UnknownRomSize(u8),
+
+    #[error("no ROM Size code for {0:#X} bytes")]
+    UnencodableRomSize(usize),
+
+    #[error("no RAM Size code for {0:#X} bytes")]
+    UnencodableRamSize(usize),
+
+    #[error("title must be at most {0} ASCII characters")]
+    InvalidTitle(usize),
+
+    #[error("ROM is too small: {0:#X}")]
+    RomTooSmall(usize),
+
+    #[error("bank 1 mirrors bank 0 after switching banks, the cartridge may not work at 3.3V")]
+    BankMirror,
+
+    #[error("invalid progress file: {0}")]
+    InvalidResumeLog(String),
+
+    #[error("the cartridge header does not match the interrupted dump")]
+    ResumeMismatch,
+
+    #[error("interrupted")]
+    Interrupted,
+
+    #[error("invalid DAT file: {0}")]
+    InvalidDat(String),
+
+    #[error("invalid output template: {0}")]
+    InvalidTemplate(String),
+}
+
+// Readの実装はio::Errorに包んで返すため、中身がErrorであれば取り出す
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.get_ref().map(|inner| inner.is::<Error>()) {
+            Some(true) => *e.into_inner().unwrap().downcast::<Error>().unwrap(),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,223 @@
+use crate::board::CubicStyleBoard;
+use crate::error::Result;
+use crate::mbc::{Mbc5Reader, MbcReader, RamReader};
+use crate::rom::{RamSize, RomHeader, RomSize};
+
+// @see https://github.com/sanni/cartreader (GBM.ino)
+
+pub const FLASH_SIZE: usize = 0x100000;
+pub const SRAM_SIZE: usize = 0x20000;
+pub const MAP_SIZE: usize = 0x80;
+
+const MAP_ENTRY_SIZE: usize = 3;
+const MAP_ENTRY_COUNT: usize = 8;
+
+pub struct GbMemory<'a> {
+    board: &'a mut CubicStyleBoard,
+}
+
+impl<'a> GbMemory<'a> {
+    pub fn new(board: &'a mut CubicStyleBoard) -> Self {
+        Self { board }
+    }
+
+    // 0x0120-0x013Fのポートへのアクセスを有効化する（最初のコマンドの前に必要）
+    pub fn wakeup(&mut self) -> Result<()> {
+        self.write(0x0120, 0x09)?;
+        self.write(0x0121, 0xAA)?;
+        self.write(0x0122, 0x55)?;
+        self.write(0x013F, 0xA5)?;
+
+        Ok(())
+    }
+
+    // メニューのマッパーを解除し、フラッシュ全体をMBC5としてマップする
+    pub fn map_entire_flash(&mut self) -> Result<()> {
+        self.send_command(0x04)
+    }
+
+    pub fn read_map(&mut self) -> Result<[u8; MAP_SIZE]> {
+        // WE/WPの解除
+        self.write(0x0120, 0x0A)?;
+        self.write(0x0125, 0x62)?;
+        self.write(0x0126, 0x04)?;
+        self.write(0x013F, 0xA5)?;
+
+        // 隠しマップ領域の有効化
+        self.write(0x2100, 0x01)?;
+
+        for _ in 0..2 {
+            self.send_flash_command(0x5555, 0xAA)?;
+            self.send_flash_command(0x2AAA, 0x55)?;
+            self.send_flash_command(0x5555, 0x77)?;
+        }
+
+        let mut map = [0; MAP_SIZE];
+
+        for (addr, data) in map.iter_mut().enumerate() {
+            self.board.set_addr(addr as u16);
+            *data = self.board.read_byte()?;
+        }
+
+        // フラッシュをリセットして隠しマップ領域から抜ける
+        self.send_flash_command(0x5555, 0xAA)?;
+        self.send_flash_command(0x2AAA, 0x55)?;
+        self.send_flash_command(0x5555, 0xF0)?;
+
+        Ok(map)
+    }
+
+    pub fn into_flash_reader(self) -> Box<dyn MbcReader + 'a> {
+        let header = RomHeader {
+            rom_size: RomSize::Bytes(FLASH_SIZE),
+            ..Default::default()
+        };
+
+        Box::new(Mbc5Reader::new(self.board, header))
+    }
+
+    pub fn into_sram_reader(self) -> Box<dyn MbcReader + 'a> {
+        let header = RomHeader {
+            ram_size: RamSize::Bytes(SRAM_SIZE),
+            ..Default::default()
+        };
+
+        Box::new(RamReader::new(self.board, header, 0b00001111))
+    }
+
+    fn send_command(&mut self, cmd: u8) -> Result<()> {
+        self.write(0x0120, cmd)?;
+        self.write(0x013F, 0xA5)?;
+
+        Ok(())
+    }
+
+    fn send_flash_command(&mut self, addr: u16, val: u8) -> Result<()> {
+        self.write(0x0120, 0x0F)?;
+        self.write(0x0125, (addr >> 8) as u8)?;
+        self.write(0x0126, (addr & 0xFF) as u8)?;
+        self.write(0x0127, val)?;
+        self.write(0x013F, 0xA5)?;
+
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> Result<()> {
+        self.board.set_addr(addr);
+        self.board.write_byte(val)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MapEntry {
+    pub mbc: u8,
+    pub rom_offset: usize,
+    pub rom_size: usize,
+    pub ram_offset: usize,
+    pub ram_size: usize,
+}
+
+impl MapEntry {
+    // 1エントリ3バイト
+    // byte0: bit7-5 MBC, bit4-2 ROMサイズ, bit1-0 RAMサイズ上位
+    // byte1: bit7 RAMサイズ下位, bit6-0 ROMオフセット(32KiB単位)
+    // byte2: RAMオフセット(2KiB単位)
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.iter().all(|&b| b == 0xFF) || bytes.iter().all(|&b| b == 0x00) {
+            return None;
+        }
+
+        let ram_size_code = ((bytes[0] & 0b00000011) << 1) | (bytes[1] >> 7);
+
+        Some(Self {
+            mbc: bytes[0] >> 5,
+            rom_offset: (bytes[1] & 0b01111111) as usize * 0x8000,
+            rom_size: 0x8000 << ((bytes[0] >> 2) & 0b00000111),
+            ram_offset: bytes[2] as usize * 0x0800,
+            ram_size: if ram_size_code == 0 {
+                0
+            } else {
+                0x0800 << (ram_size_code - 1)
+            },
+        })
+    }
+}
+
+pub fn parse_map(map: &[u8]) -> Vec<MapEntry> {
+    map.chunks(MAP_ENTRY_SIZE)
+        .take(MAP_ENTRY_COUNT)
+        .filter_map(MapEntry::from_bytes)
+        .filter(|e| e.rom_offset + e.rom_size <= FLASH_SIZE)
+        .collect()
+}
+
+pub fn split_flash<'f>(flash: &'f [u8], map: &[u8]) -> Vec<(MapEntry, &'f [u8])> {
+    parse_map(map)
+        .into_iter()
+        .filter(|e| e.rom_offset + e.rom_size <= flash.len())
+        .map(|e| (e, &flash[e.rom_offset..e.rom_offset + e.rom_size]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // MBC1, 128KiB ROM(オフセット0x20000), 8KiB RAM(オフセット0x8000)
+    // MBC=001, ROMサイズ=010, RAMサイズ=011, ROMオフセット=0000100
+    const ENTRY: [u8; 3] = [0b00101001, 0b10000100, 0x10];
+
+    #[test]
+    fn from_bytes_decodes_bit_fields() {
+        let entry = MapEntry::from_bytes(&ENTRY).unwrap();
+
+        assert_eq!(entry.mbc, 1);
+        assert_eq!(entry.rom_offset, 0x20000);
+        assert_eq!(entry.rom_size, 0x20000);
+        assert_eq!(entry.ram_offset, 0x8000);
+        assert_eq!(entry.ram_size, 0x2000);
+    }
+
+    #[test]
+    fn from_bytes_decodes_entry_without_ram() {
+        let entry = MapEntry::from_bytes(&[0b10100000, 0b00000000, 0x00]).unwrap();
+
+        assert_eq!(entry.mbc, 5);
+        assert_eq!(entry.rom_offset, 0);
+        assert_eq!(entry.rom_size, 0x8000);
+        assert_eq!(entry.ram_size, 0);
+    }
+
+    #[test]
+    fn from_bytes_skips_empty_entries() {
+        assert!(MapEntry::from_bytes(&[0x00; 3]).is_none());
+        assert!(MapEntry::from_bytes(&[0xFF; 3]).is_none());
+    }
+
+    #[test]
+    fn parse_map_skips_empty_and_out_of_range_entries() {
+        let mut map = vec![0xFF; MAP_SIZE];
+        map[0..3].copy_from_slice(&ENTRY);
+        // 0x7F * 32KiB から128KiBはフラッシュの範囲外
+        map[3..6].copy_from_slice(&[0b00101000, 0b01111111, 0x00]);
+        map[6..9].copy_from_slice(&[0x00; 3]);
+
+        let entries = parse_map(&map);
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].rom_offset, 0x20000);
+    }
+
+    #[test]
+    fn split_flash_slices_each_rom() {
+        let flash = (0..FLASH_SIZE).map(|i| (i >> 15) as u8).collect::<Vec<_>>();
+
+        let mut map = vec![0xFF; MAP_SIZE];
+        map[0..3].copy_from_slice(&ENTRY);
+
+        let roms = split_flash(&flash, &map);
+
+        assert_eq!(roms.len(), 1);
+        assert_eq!(roms[0].1, &flash[0x20000..0x40000]);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::mbc::MbcReader;
+use crate::utils::bytes_to_hex;
+use crc32fast::Hasher as Crc32;
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::io::{self, Read, Seek, SeekFrom};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hashes {
+    pub size: usize,
+    pub crc32: u32,
+    pub md5: [u8; 16],
+    pub sha1: [u8; 20],
+    pub sha256: [u8; 32],
+}
+
+impl Hashes {
+    pub fn of(data: &[u8]) -> Self {
+        let mut hasher = Hasher::default();
+
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    pub fn crc32_hex(&self) -> String {
+        format!("{:08X}", self.crc32)
+    }
+
+    pub fn md5_hex(&self) -> String {
+        bytes_to_hex(&self.md5)
+    }
+
+    pub fn sha1_hex(&self) -> String {
+        bytes_to_hex(&self.sha1)
+    }
+
+    pub fn sha256_hex(&self) -> String {
+        bytes_to_hex(&self.sha256)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Hasher {
+    size: usize,
+    crc32: Crc32,
+    md5: Md5,
+    sha1: Sha1,
+    sha256: Sha256,
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        self.size += data.len();
+        self.crc32.update(data);
+        self.md5.update(data);
+        self.sha1.update(data);
+        self.sha256.update(data);
+    }
+
+    pub fn finish(self) -> Hashes {
+        Hashes {
+            size: self.size,
+            crc32: self.crc32.finalize(),
+            md5: self.md5.finalize().into(),
+            sha1: self.sha1.finalize().into(),
+            sha256: self.sha256.finalize().into(),
+        }
+    }
+}
+
+// 読み出したデータをそのままハッシュに通すリーダー
+// 読み直しなどで現在位置以外へシークした場合は、出力と異なるデータを含む可能性があるためハッシュを無効にする
+pub struct HashingReader<R: MbcReader> {
+    inner: R,
+
+    hasher: Hasher,
+    pos: u64,
+    sequential: bool,
+}
+
+impl<R: MbcReader> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Hasher::default(),
+            pos: 0,
+            sequential: true,
+        }
+    }
+
+    // 先頭から最後まで順に読み出した場合だけハッシュを返す
+    pub fn hashes(&self) -> Option<Hashes> {
+        if self.sequential && self.pos == self.inner.size() as u64 {
+            Some(self.hasher.clone().finish())
+        } else {
+            None
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: MbcReader> MbcReader for HashingReader<R> {
+    fn size(&self) -> usize {
+        self.inner.size()
+    }
+
+    fn status(&self) -> String {
+        self.inner.status()
+    }
+}
+
+impl<R: MbcReader> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+
+        self.hasher.update(&buf[..n]);
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl<R: MbcReader> Seek for HashingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = self.inner.seek(pos)?;
+
+        if pos != self.pos {
+            self.sequential = false;
+        }
+
+        self.pos = pos;
+
+        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::testing::MemoryReader;
+
+    #[test]
+    fn sequential_read_hashes_whole_rom() {
+        let data = (0..0x8000).map(|i| i as u8).collect::<Vec<_>>();
+        let mut reader = HashingReader::new(MemoryReader::new(data.clone(), &[]));
+
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        reader.read_to_end(&mut Vec::new()).unwrap();
+
+        assert_eq!(reader.hashes().unwrap().sha1, Hashes::of(&data).sha1);
+    }
+
+    #[test]
+    fn rereading_invalidates_hashes() {
+        let data = vec![0xFF; 0x8000];
+        let mut reader = HashingReader::new(MemoryReader::new(data, &[]));
+
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        reader.read_to_end(&mut Vec::new()).unwrap();
+
+        assert!(reader.hashes().is_none());
+    }
+}
@@ -1,4 +1,12 @@
 pub mod mbc;
 pub mod board;
+pub mod dat;
+pub mod dump;
+pub mod error;
+pub mod gbmemory;
+pub mod hash;
+pub mod licensee;
+pub mod probe;
 pub mod rom;
+pub mod template;
 pub mod utils;
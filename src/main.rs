@@ -1,10 +1,51 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::{AppSettings, Clap};
-use gb_reader::{board::CubicStyleBoard, mbc::new_mbc_reader, mbc::new_repl_mbc_reader};
+use gb_reader::{
+    board::CubicStyleBoard, dat::Dat, dat::DatEntry, dat::Lookup, dump::read_multi_pass,
+    dump::retry_size, dump::retry_until_checksum, dump::Progress, dump::ResumeLog, dump::BANK_SIZE,
+    error::Error, error::Result as LibResult, gbmemory, gbmemory::GbMemory, hash::Hashes,
+    hash::HashingReader, licensee::USE_NEW_LICENSEE_CODE, mbc::check_ram_support,
+    mbc::new_mbc_reader, mbc::new_ram_reader, mbc::new_ram_writer, mbc::new_repl_mbc_reader,
+    mbc::Mapper, mbc::MbcReader, mbc::MbcWriter, mbc::ReaderOptions, mbc::RomHeaderReader,
+    probe::probe_mapper, rom::fix_checksums, rom::global_checksum, rom::header_checksum,
+    rom::CgbSupport, rom::DestinationCode, rom::GlobalChecksum, rom::RomHeader, rom::RomSize,
+    rom::SgbFlag, template::FileNameTemplate,
+};
 use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
-use std::fs::File;
-use std::io::{Read as _, Write as _};
-use std::str;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read as _, Seek as _, SeekFrom, Write as _};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// ライブラリからの警告を表示する
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        match record.level() {
+            Level::Error => eprintln!("エラー: {}", record.args()),
+            _ => eprintln!("警告: {}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Logger = Logger;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
 #[derive(Clap)]
 #[clap(version = "0.1.0", author = "mjhd <mjhd.devlion@gmail.com>")]
@@ -17,69 +58,1119 @@ struct Opts {
 #[derive(Clap)]
 enum SubCommand {
     Read(Read),
+    GbMemory(GbMemoryRead),
+    Fix(Fix),
+    RestoreSram(RestoreSram),
+    Info(Info),
+    Verify(Verify),
 }
 
 #[derive(Clap)]
 struct Read {
+    /// 出力ファイル名。{title}, {code}, {version}, {ext}, {crc32}, {md5}, {sha1}, {sha256} を置き換える (既定: {title}.{ext})
     #[clap(short, long)]
-    output: String,
+    output: Option<FileNameTemplate>,
+
+    /// 出力先のディレクトリ
+    #[clap(long)]
+    output_dir: Option<String>,
 
     #[clap(short, long)]
     repl: bool,
+
+    #[clap(long)]
+    sram: Option<String>,
+
+    /// rom-only, mbc1, mbc2, mbc3, mbc30, mbc5, wisdom-tree, sachen-mmc1, sachen-mmc2, m161
+    #[clap(short, long)]
+    mapper: Option<Mapper>,
+
+    /// ROMサイズ(KiB)
+    #[clap(long)]
+    rom_size: Option<usize>,
+
+    /// バンクレジスタへの書き込み結果からマッパーとROMサイズを推定する
+    #[clap(short, long)]
+    probe: bool,
+
+    /// ヘッダのROMサイズが有効な場合も、バンクのミラーから検出したROMサイズで読み出す
+    #[clap(long)]
+    detect_size: bool,
+
+    /// 未対応のマッパーの場合も、先頭32KiBだけ読み出す
+    #[clap(long)]
+    fallback: bool,
+
+    /// ROM全体を読み出す回数。2以上を指定すると、一致しなかったバンクを読み直して多数決を取る
+    #[clap(long, default_value = "1")]
+    passes: usize,
+
+    /// グローバルチェックサムが一致しない場合に、全バンクを読み直す最大回数
+    #[clap(long, default_value = "3")]
+    retries: usize,
+
+    /// 中断した読み出しを、進捗ファイル(<出力ファイル>.progress)をもとに再開する
+    #[clap(long)]
+    resume: bool,
+
+    /// ROMのハッシュ値(CRC32/MD5/SHA-1/SHA-256)を書き出すファイル
+    #[clap(long)]
+    hash_report: Option<String>,
+
+    /// 照合に使うLogiqx XML形式のDATファイル(No-Intro等)
+    #[clap(long)]
+    dat: Option<String>,
+
+    /// DATと一致した場合、出力ファイルの名前をDATのROM名に変更する
+    #[clap(long)]
+    name_from_dat: bool,
 }
 
-fn read_rom(output: String, repl: bool) -> Result<()> {
-    println!("[0/4] 拡張ボードの初期化中...");
-    let mut board = CubicStyleBoard::new()?;
+#[derive(Clap)]
+struct GbMemoryRead {
+    #[clap(short, long)]
+    output: String,
 
-    println!("[1/4] ROMヘッダの解析中...");
-    let (mut reader, header) = if repl {
-        new_repl_mbc_reader(&mut board)?
-    } else {
-        new_mbc_reader(&mut board)?
-    };
+    #[clap(long)]
+    sram: Option<String>,
 
-    println!(
-        "タイトル: {}, MBC: {:?}, ROMサイズ: {}",
-        str::from_utf8(&header.title[..]).unwrap_or("ERR"),
-        header.mbc_type,
-        HumanBytes(header.rom_size as u64)
-    );
+    #[clap(long)]
+    map: Option<String>,
+
+    /// 分割したROMの出力先ディレクトリ
+    #[clap(long)]
+    split: Option<String>,
+}
+
+#[derive(Clap)]
+struct Fix {
+    #[clap(short, long)]
+    file: String,
+
+    #[clap(long)]
+    title: Option<String>,
+
+    /// カートリッジタイプ(16進数)
+    #[clap(long, parse(try_from_str = parse_hex_u8))]
+    cartridge_type: Option<u8>,
+}
+
+#[derive(Clap)]
+struct RestoreSram {
+    /// 書き込むセーブデータ
+    #[clap(short, long)]
+    input: String,
+}
 
-    println!("[2/4] 出力ファイルの作成中...");
-    let mut file = File::create(output)?;
+#[derive(Clap)]
+struct Info {
+    /// カートリッジの代わりに読み込むROMファイル
+    #[clap(short, long)]
+    file: Option<String>,
 
-    let total = reader.size();
+    /// JSON形式で出力する
+    #[clap(long)]
+    json: bool,
+}
 
-    let reading = ProgressBar::new(total as u64);
-    reading.set_style(
+#[derive(Clap)]
+struct Verify {
+    /// 比較するROMファイル
+    #[clap(short, long)]
+    file: String,
+
+    /// rom-only, mbc1, mbc2, mbc3, mbc30, mbc5, wisdom-tree, sachen-mmc1, sachen-mmc2, m161
+    #[clap(short, long)]
+    mapper: Option<Mapper>,
+
+    /// ROMサイズ(KiB)
+    #[clap(long)]
+    rom_size: Option<usize>,
+}
+
+fn parse_hex_u8(s: &str) -> Result<u8> {
+    Ok(u8::from_str_radix(s.trim_start_matches("0x"), 16)?)
+}
+
+fn progress_bar(total: usize) -> ProgressBar {
+    let bar = ProgressBar::new(total as u64);
+    bar.set_style(
         ProgressStyle::default_bar()
             .template("[{elapsed_precise}({eta})] {msg} [{bar:.cyan/blue}] {bytes}/{total_bytes}")
             .progress_chars("#>-"),
     );
 
-    println!("[3/4] ROM読み込み中...");
+    bar
+}
+
+fn read_all(
+    reader: &mut dyn MbcReader,
+    mut on_read: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let reading = progress_bar(reader.size());
+
+    // 途中から再開した場合は、読み出し済みの分から進捗を表示する
+    reading.set_position(reader.stream_position().map_err(Error::from)?);
 
     loop {
+        check_interrupted()?;
+
         let mut buffer = [0; 0x0100];
 
-        let size = reader.read(&mut buffer)?;
+        // 読み出し中のエラーはio::Errorに包まれているため、ライブラリのエラーに戻す
+        let size = reader.read(&mut buffer).map_err(Error::from)?;
 
         if size == 0 {
             break;
         }
 
-        file.write(&buffer[0..size])?;
+        on_read(&buffer[0..size])?;
 
         reading.inc(size as u64);
         reading.set_message(&reader.status());
     }
 
+    reading.finish_and_clear();
+
+    Ok(())
+}
+
+fn update_progress(bar: &ProgressBar) -> impl FnMut(&Progress) -> LibResult<()> + '_ {
+    move |progress| {
+        bar.set_length(progress.total as u64);
+        bar.set_position(progress.done as u64);
+        bar.set_message(&progress.status);
+
+        check_interrupted()
+    }
+}
+
+// Ctrl-Cが押されていれば、読み書き中の256バイトのチャンクを終えたところで中断する
+fn check_interrupted() -> LibResult<()> {
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        return Err(Error::Interrupted);
+    }
+
+    Ok(())
+}
+
+// 書き込み中のファイルは.partを付けておき、完了してからリネームする
+fn part_path(path: &str) -> String {
+    format!("{}.part", path)
+}
+
+fn write_atomic(path: &str, data: &[u8]) -> Result<()> {
+    let part = part_path(path);
+
+    // 電源断でリネーム後のファイルが空にならないよう、ディスクに書き込んでからリネームする
+    let mut file = File::create(&part)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+
+    fs::rename(&part, path)?;
+
+    Ok(())
+}
+
+fn dump(reader: &mut dyn MbcReader, output: &str, mut on_read: impl FnMut(&[u8])) -> Result<()> {
+    let part = part_path(output);
+    let mut file = File::create(&part)?;
+
+    let result = read_all(reader, |data| {
+        file.write_all(data)?;
+        on_read(data);
+
+        Ok(())
+    })
+    .and_then(|()| Ok(file.sync_all()?));
+
+    // 再開できないため、途中までのファイルは残さない
+    if result.is_err() {
+        drop(file);
+        let _ = fs::remove_file(&part);
+    }
+
+    result?;
+
+    fs::rename(&part, output)?;
+
+    Ok(())
+}
+
+// 読み終えたバンクをResumeLogに記録しながら、未完了のバンクから読み出す
+fn dump_resumable(
+    reader: &mut dyn MbcReader,
+    output: &str,
+    log: &mut ResumeLog,
+    mut on_read: impl FnMut(&[u8]),
+) -> Result<()> {
+    // 出力ファイルが無ければ最初から読み出す。読めない場合は進捗を失わないようエラーにする
+    let mut rom = match fs::read(output) {
+        Ok(rom) => rom,
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let start = log.verify(&rom)? * BANK_SIZE;
+
+    if start > 0 {
+        println!(
+            "{}バンク読み出し済みのため、{:#08X}から読み出しを再開します",
+            log.completed_banks(),
+            start
+        );
+    }
+
+    rom.truncate(start);
+    on_read(&rom);
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(output)?;
+    file.set_len(start as u64)?;
+    file.seek(SeekFrom::End(0))?;
+
+    reader
+        .seek(SeekFrom::Start(start as u64))
+        .map_err(Error::from)?;
+
+    let mut bank = Vec::with_capacity(BANK_SIZE);
+
+    read_all(reader, |data| {
+        file.write_all(data)?;
+        on_read(data);
+
+        bank.extend_from_slice(data);
+
+        // ファイルに書き込んでからバンクを完了として記録する
+        if bank.len() == BANK_SIZE {
+            file.sync_data()?;
+            log.push(&bank)?;
+
+            bank.clear();
+        }
+
+        Ok(())
+    })?;
+
+    file.sync_all()?;
+
+    if !bank.is_empty() {
+        log.push(&bank)?;
+    }
+
+    Ok(())
+}
+
+fn restore(writer: &mut dyn MbcWriter, input: &str) -> Result<()> {
+    let data = fs::read(input)?;
+    let total = writer.size();
+
+    if data.len() < total {
+        bail!(
+            "save file is too small (expected: {:#X}, actual: {:#X})",
+            total,
+            data.len()
+        );
+    }
+
+    // MBC3のRTCなど、末尾に付加されたデータは書き込まない
+    if data.len() > total {
+        eprintln!(
+            "警告: セーブデータの末尾{}バイトは書き込みません",
+            data.len() - total
+        );
+    }
+
+    let writing = progress_bar(total);
+
+    for chunk in data[..total].chunks(0x0100) {
+        check_interrupted()?;
+
+        writer.write_all(chunk).map_err(Error::from)?;
+
+        writing.inc(chunk.len() as u64);
+        writing.set_message(&writer.status());
+    }
+
+    writer.flush()?;
+
+    writing.finish_and_clear();
+
+    Ok(())
+}
+
+fn read_header_bytes(board: &mut CubicStyleBoard) -> Result<Vec<u8>> {
+    let mut header = Vec::new();
+
+    RomHeaderReader::new(board)
+        .read_to_end(&mut header)
+        .map_err(Error::from)?;
+
+    Ok(header)
+}
+
+fn check_logo(board: &mut CubicStyleBoard) -> Result<()> {
+    let header = RomHeader::from_reader_lenient(&mut RomHeaderReader::new(board))?;
+    let mismatches = header.logo_mismatches();
+
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+
+    for m in &mismatches {
+        eprintln!(
+            "{:#06X}: expected {:02X}, actual {:02X}",
+            m.addr, m.expected, m.actual
+        );
+    }
+
+    eprintln!("ロゴが一致しません。カードリッジの接触不良です。差し込み直してください");
+
+    bail!("Nintendo logo mismatch ({} bytes)", mismatches.len());
+}
+
+fn join(banks: &[usize]) -> String {
+    banks
+        .iter()
+        .map(|bank| bank.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn report_bank_mirror(e: &Error) {
+    if let Error::BankMirror = e {
+        eprintln!("バンク切り替え後も4000番地から0000番地と同じデータが読み出されました。");
+        eprintln!("拡張ボードは+3.3Vで動作しているため、一部のカードリッジが正常に動作しません。");
+        eprintln!("コネクタの1番左のVCCピンを+5Vに変更するなどの改造が必要です（自己責任でお願いします）。");
+    }
+}
+
+fn cgb_support_name(cgb_support: CgbSupport) -> &'static str {
+    match cgb_support {
+        CgbSupport::None => "非対応",
+        CgbSupport::Compatible => "対応",
+        CgbSupport::Only => "専用",
+    }
+}
+
+// DATとの照合結果を表示し、正常なダンプと一致した項目を返す
+fn report_dat<'d>(dat: &'d Dat, hashes: &Hashes) -> Option<&'d DatEntry> {
+    match dat.lookup(hashes) {
+        Lookup::Verified(entry) => {
+            println!(
+                "DAT: 一致 {} (地域: {}, リビジョン: {})",
+                entry.name,
+                entry.region().unwrap_or_else(|| "-".to_string()),
+                entry.revision().unwrap_or_else(|| "-".to_string())
+            );
+
+            Some(entry)
+        }
+        Lookup::BadDump(entry) => {
+            println!("DAT: 不良ダンプとして登録されています ({})", entry.name);
+
+            None
+        }
+        Lookup::Unknown => {
+            println!("DAT: 一致する項目がありません。未知のROMか、読み出しに失敗しています");
+
+            None
+        }
+    }
+}
+
+// テンプレートから出力ファイルのパスを決める。拡張子がなければCGB対応かどうかで.gb/.gbcを選ぶ
+fn output_path(
+    dir: Option<&str>,
+    template: &FileNameTemplate,
+    header: &RomHeader,
+    hashes: Option<&Hashes>,
+) -> String {
+    let mut name = template.render(header, hashes);
+
+    if Path::new(&name).extension().is_none() {
+        name = format!("{}.{}", name, header.extension());
+    }
+
+    match dir {
+        Some(dir) => Path::new(dir).join(name).to_string_lossy().to_string(),
+        None => name,
+    }
+}
+
+// 既にファイルがあれば、拡張子の前に_2, _3...を付けて重複しない名前にする
+fn unique_path(path: &str) -> String {
+    let path = Path::new(path);
+
+    if !path.exists() {
+        return path.to_string_lossy().to_string();
+    }
+
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    let unique = (2..)
+        .map(|i| path.with_file_name(format!("{}_{}{}", stem, i, ext)))
+        .find(|candidate| !candidate.exists())
+        .unwrap();
+
+    println!(
+        "{}が既に存在するため、{}に出力します",
+        path.display(),
+        unique.display()
+    );
+
+    unique.to_string_lossy().to_string()
+}
+
+// 出力ファイルの名前をrenamedに変更する
+fn rename_output(output: &str, renamed: &str) -> Result<String> {
+    if renamed == output {
+        return Ok(output.to_string());
+    }
+
+    if Path::new(renamed).exists() {
+        eprintln!("警告: {}が既に存在するため、名前を変更しません", renamed);
+
+        return Ok(output.to_string());
+    }
+
+    if let Some(parent) = Path::new(renamed).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::rename(output, renamed)?;
+
+    println!("出力ファイル: {}", renamed);
+
+    Ok(renamed.to_string())
+}
+
+fn write_hash_report(path: &str, output: &str, hashes: &Hashes) -> Result<()> {
+    let name = Path::new(output)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let report = format!(
+        "File: {}\nSize: {}\nCRC32: {}\nMD5: {}\nSHA-1: {}\nSHA-256: {}\n",
+        name,
+        hashes.size,
+        hashes.crc32_hex(),
+        hashes.md5_hex(),
+        hashes.sha1_hex(),
+        hashes.sha256_hex()
+    );
+
+    write_atomic(path, report.as_bytes())
+}
+
+fn read_rom(t: Read) -> Result<()> {
+    let mut options = ReaderOptions {
+        mapper: t.mapper,
+        rom_size: t.rom_size.map(|kib| kib * 1024),
+        detect_size: t.detect_size,
+        fallback: t.fallback,
+    };
+
+    let Read {
+        output,
+        output_dir,
+        repl,
+        sram,
+        mapper: user_mapper,
+        probe,
+        passes,
+        retries,
+        resume,
+        hash_report,
+        dat,
+        name_from_dat,
+        ..
+    } = t;
+
+    if resume && (repl || passes > 1) {
+        bail!("--resume cannot be combined with --repl or --passes");
+    }
+
+    // DATの形式が誤っていた場合に読み出しが無駄にならないよう、先に読み込んでおく
+    let dat = dat.map(Dat::from_file).transpose()?;
+
+    println!("[0/4] 拡張ボードの初期化中...");
+    let mut board = CubicStyleBoard::new()?;
+
+    // 非正規カートリッジはロゴが異なることがあるため、マッパー指定時は検証しない
+    if !repl && options.mapper.is_none() {
+        println!("ロゴの検証中...");
+        check_logo(&mut board)?;
+    }
+
+    if probe {
+        println!("マッパーの推定中...");
+        let probe = probe_mapper(&mut board)?;
+
+        println!(
+            "推定マッパー: {:?}, 推定ROMサイズ: {}",
+            probe.mapper,
+            HumanBytes(probe.rom_size() as u64)
+        );
+
+        options.mapper.get_or_insert(probe.mapper);
+        options.rom_size.get_or_insert(probe.rom_size());
+    }
+
+    println!("[1/4] ROMヘッダの解析中...");
+    let header_bytes = read_header_bytes(&mut board)?;
+
+    let (reader, header) = if repl {
+        new_repl_mbc_reader(&mut board)?
+    } else {
+        new_mbc_reader(&mut board, options).inspect_err(report_bank_mirror)?
+    };
+
+    // ROMを読み出した後で失敗しないよう、SRAMに対応しているかを先に確認する
+    if sram.is_some() {
+        check_ram_support(&header)?;
+    }
+
+    let mut reader = HashingReader::new(reader);
+
+    // 未対応のマッパーはフォールバックしてRomOnlyとして読み出している
+    let mapper = options
+        .mapper
+        .unwrap_or_else(|| Mapper::from_header(&header).unwrap_or(Mapper::RomOnly));
+
+    println!(
+        "タイトル: {}, 発売元: {}, メーカーコード: {}, CGB: {}, MBC: {:?}, ROMサイズ: {}",
+        header.title(),
+        header.publisher().unwrap_or("不明"),
+        header
+            .manufacturer_code()
+            .unwrap_or_else(|| "-".to_string()),
+        cgb_support_name(header.cgb_support),
+        mapper,
+        HumanBytes(header.rom_size.bytes().unwrap_or_default() as u64)
+    );
+
+    // ハッシュを含むテンプレートは、読み出し後に名前を確定させる
+    let template = output.unwrap_or_default();
+    let mut output = output_path(output_dir.as_deref(), &template, &header, None);
+
+    // 同じタイトルのカートリッジを続けて読み出しても上書きしないよう、生成した名前は重複を避ける
+    // 既存のファイルだけを見て決めるため、--resumeでも中断前と同じ名前になる
+    if !template.is_literal() {
+        output = unique_path(&output);
+    }
+
+    if let Some(parent) = Path::new(&output).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    println!("[2/4] ROM読み込み中... ({})", output);
+    let mut checksum = GlobalChecksum::default();
+
+    if passes > 1 {
+        let reading = progress_bar(reader.size() * passes);
+
+        let (rom, report) = read_multi_pass(&mut reader, passes, update_progress(&reading))?;
+
+        reading.finish_and_clear();
+
+        write_atomic(&output, &rom)?;
+        checksum.update(&rom);
+
+        if report.unstable_banks.is_empty() {
+            println!("{}回の読み出し結果はすべて一致しました", passes);
+        } else {
+            println!(
+                "不安定なバンク: {}, 多数決で補正したバイト数: {}",
+                join(&report.unstable_banks),
+                report.corrected_bytes
+            );
+        }
+    } else if repl {
+        dump(&mut reader, &output, |data| checksum.update(data))?;
+    } else {
+        let log_path = format!("{}.progress", output);
+
+        let mut log = if resume {
+            let log = ResumeLog::open(&log_path)?;
+
+            if !log.matches(&header_bytes, reader.size()) {
+                return Err(Error::ResumeMismatch.into());
+            }
+
+            log
+        } else {
+            ResumeLog::create(&log_path, &header_bytes, reader.size())?
+        };
+
+        let part = part_path(&output);
+
+        dump_resumable(&mut reader, &part, &mut log, |data| checksum.update(data)).inspect_err(
+            |e| {
+                if let Some(Error::Interrupted) = e.downcast_ref::<Error>() {
+                    eprintln!(
+                        "読み出し途中のROMを{}に残しました。--resume で再開できます。",
+                        part
+                    );
+                }
+            },
+        )?;
+
+        fs::rename(&part, &output)?;
+        log.remove()?;
+    }
+
+    let expected = header.expected_global_checksum();
+
+    // フォールバックやサイズ・マッパーの指定でヘッダと異なる読み出し方をした場合、
+    // チェックサムは一致しないのが当然なので読み直さない
+    let checksum_applies = user_mapper.is_none()
+        && RomSize::from_code(header_bytes[0x0148]).bytes() == Some(reader.size());
+
+    // チェックサムが一致しなければ、バンクごとに読み直して多数決を取る
+    if !repl && retries > 0 && checksum_applies && checksum.value() != expected {
+        println!("グローバルチェックサムが一致しないため、バンクを読み直しています...");
+        let mut rom = fs::read(&output)?;
+
+        let reading = progress_bar(retry_size(reader.size(), retries));
+
+        let report = retry_until_checksum(
+            &mut reader,
+            &mut rom,
+            expected,
+            retries,
+            update_progress(&reading),
+        )?;
+
+        reading.finish_and_clear();
+
+        write_atomic(&output, &rom)?;
+
+        checksum = GlobalChecksum::default();
+        checksum.update(&rom);
+
+        if report.unstable_banks.is_empty() {
+            println!(
+                "{}回読み直しましたが、内容は変わりませんでした",
+                report.rounds
+            );
+        } else {
+            println!(
+                "{}回読み直しました。不安定なバンク: {}",
+                report.rounds,
+                join(&report.unstable_banks)
+            );
+        }
+    }
+
+    // 読み直しなどで読み出し順が崩れた場合は、出力したファイルから計算し直す
+    let hashes = match reader.hashes() {
+        Some(hashes) => hashes,
+        None => Hashes::of(&fs::read(&output)?),
+    };
+
+    drop(reader);
+
+    if let Some(sram) = sram {
+        println!("[3/4] SRAM読み込み中...");
+        let mut reader = new_ram_reader(&mut board, header)?;
+
+        println!("RAMサイズ: {}", HumanBytes(reader.size() as u64));
+
+        dump(&mut reader, &sram, |_| {})?;
+    }
+
     println!("[4/4] 仕上げ中...");
-    file.flush()?;
+
+    if !repl {
+        if checksum.value() == expected {
+            println!("グローバルチェックサム: OK ({:04X})", expected);
+        } else {
+            println!(
+                "グローバルチェックサム: NG (期待値: {:04X}, 実際: {:04X}) 読み出し直してください",
+                expected,
+                checksum.value()
+            );
+        }
+    }
+
+    let output = if template.needs_hashes() {
+        let renamed = output_path(output_dir.as_deref(), &template, &header, Some(&hashes));
+
+        rename_output(&output, &unique_path(&renamed))?
+    } else {
+        output
+    };
+
+    let output = match dat.as_ref().and_then(|dat| report_dat(dat, &hashes)) {
+        Some(entry) if name_from_dat => {
+            let renamed = Path::new(&output).with_file_name(&entry.rom_name);
+
+            rename_output(&output, &renamed.to_string_lossy())?
+        }
+        _ => output,
+    };
+
+    println!("CRC32: {}", hashes.crc32_hex());
+    println!("MD5: {}", hashes.md5_hex());
+    println!("SHA-1: {}", hashes.sha1_hex());
+    println!("SHA-256: {}", hashes.sha256_hex());
+
+    if let Some(hash_report) = hash_report {
+        write_hash_report(&hash_report, &output, &hashes)?;
+    }
 
     println!("完了！");
-    reading.finish_and_clear();
+
+    Ok(())
+}
+
+fn verify_rom(file: String, options: ReaderOptions) -> Result<()> {
+    let expected = fs::read(&file)?;
+
+    println!("[0/3] 拡張ボードの初期化中...");
+    let mut board = CubicStyleBoard::new()?;
+
+    if options.mapper.is_none() {
+        println!("ロゴの検証中...");
+        check_logo(&mut board)?;
+    }
+
+    println!("[1/3] ROMヘッダの解析中...");
+    let (mut reader, header) =
+        new_mbc_reader(&mut board, options).inspect_err(report_bank_mirror)?;
+
+    println!(
+        "タイトル: {}, ROMサイズ: {}",
+        header.title(),
+        HumanBytes(reader.size() as u64)
+    );
+
+    if reader.size() != expected.len() {
+        eprintln!(
+            "警告: サイズが異なります (カートリッジ: {}, ファイル: {})",
+            HumanBytes(reader.size() as u64),
+            HumanBytes(expected.len() as u64)
+        );
+    }
+
+    println!("[2/3] ROM比較中... ({})", file);
+    let mut offset = 0;
+    let mut first_mismatch = None;
+    let mut banks = BTreeSet::new();
+
+    read_all(reader.as_mut(), |data| {
+        for (i, &b) in data.iter().enumerate() {
+            if expected.get(offset + i) != Some(&b) {
+                first_mismatch.get_or_insert(offset + i);
+                banks.insert((offset + i) / 0x4000);
+            }
+        }
+
+        offset += data.len();
+
+        Ok(())
+    })?;
+
+    // ファイルの方が大きければ、読み出せなかった残りのバンクも不一致とする
+    if expected.len() > offset {
+        first_mismatch.get_or_insert(offset);
+        banks.extend(offset / 0x4000..=(expected.len() - 1) / 0x4000);
+    }
+
+    println!("[3/3] 仕上げ中...");
+
+    if let Some(first_mismatch) = first_mismatch {
+        println!("最初の不一致: {:#08X}", first_mismatch);
+        println!(
+            "不一致のバンク: {}",
+            join(&banks.iter().copied().collect::<Vec<_>>())
+        );
+
+        bail!("{} banks differ from {}", banks.len(), file);
+    }
+
+    println!("一致しました！");
+
+    Ok(())
+}
+
+fn read_gb_memory(
+    output: String,
+    sram: Option<String>,
+    map: Option<String>,
+    split: Option<String>,
+) -> Result<()> {
+    println!("[0/5] 拡張ボードの初期化中...");
+    let mut board = CubicStyleBoard::new()?;
+
+    println!("[1/5] マップ領域の読み込み中...");
+    let mapping = {
+        let mut cart = GbMemory::new(&mut board);
+
+        cart.wakeup()?;
+        cart.read_map()?
+    };
+
+    if let Some(map) = map {
+        write_atomic(&map, &mapping[..])?;
+    }
+
+    println!("[2/5] フラッシュ読み込み中...");
+    {
+        let mut cart = GbMemory::new(&mut board);
+
+        cart.map_entire_flash()?;
+
+        dump(cart.into_flash_reader().as_mut(), &output, |_| {})?;
+    }
+
+    if let Some(sram) = sram {
+        println!("[3/5] SRAM読み込み中...");
+        dump(
+            GbMemory::new(&mut board).into_sram_reader().as_mut(),
+            &sram,
+            |_| {},
+        )?;
+    }
+
+    if let Some(split) = split {
+        println!("[4/5] ROMの分割中...");
+        let flash = fs::read(&output)?;
+
+        fs::create_dir_all(&split)?;
+
+        for (i, (entry, rom)) in gbmemory::split_flash(&flash, &mapping)
+            .into_iter()
+            .enumerate()
+        {
+            let header = RomHeader::from_bytes_lenient(rom)?;
+            let title = header
+                .title()
+                .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+            let path = Path::new(&split).join(format!("{:02}_{}.{}", i, title, header.extension()));
+
+            println!(
+                "{}: {:#08X} - {}",
+                path.display(),
+                entry.rom_offset,
+                HumanBytes(entry.rom_size as u64)
+            );
+
+            write_atomic(&path.to_string_lossy(), rom)?;
+
+            check_interrupted()?;
+        }
+    }
+
+    println!("[5/5] 仕上げ中...");
+    println!("完了！");
+
+    Ok(())
+}
+
+fn fix_rom(file: String, title: Option<String>, cartridge_type: Option<u8>) -> Result<()> {
+    let mut rom = fs::read(&file)?;
+    let mut header = RomHeader::from_bytes_lenient(&rom)?;
+
+    if let Some(title) = title {
+        header.set_title(&title)?;
+    }
+
+    if let Some(cartridge_type) = cartridge_type {
+        header.set_cartridge_type(cartridge_type);
+    }
+
+    rom[0x0100..0x0150].copy_from_slice(&header.to_bytes()?);
+
+    let (header_checksum, global_checksum) = fix_checksums(&mut rom)?;
+
+    write_atomic(&file, &rom)?;
+
+    println!(
+        "ヘッダチェックサム: {:02X}, グローバルチェックサム: {:04X}",
+        header_checksum, global_checksum
+    );
+
+    Ok(())
+}
+
+fn restore_sram(input: String) -> Result<()> {
+    println!("[0/3] 拡張ボードの初期化中...");
+    let mut board = CubicStyleBoard::new()?;
+
+    // 接触不良のまま書き込むとセーブデータを壊してしまう
+    println!("ロゴの検証中...");
+    check_logo(&mut board)?;
+
+    println!("[1/3] ROMヘッダの解析中...");
+    let header = RomHeader::from_reader(&mut RomHeaderReader::new(&mut board))?;
+
+    println!("[2/3] SRAM書き込み中...");
+    let mut writer = new_ram_writer(&mut board, header)?;
+
+    println!("RAMサイズ: {}", HumanBytes(writer.size() as u64));
+
+    restore(writer.as_mut(), &input)?;
+
+    println!("[3/3] 仕上げ中...");
+    println!("完了！");
+
+    Ok(())
+}
+
+fn print_info(file: Option<String>, json: bool) -> Result<()> {
+    // カートリッジからはヘッダ部分だけを読み出すため、グローバルチェックサムは検証できない
+    let rom = match &file {
+        Some(file) => fs::read(file)?,
+        None => read_header_bytes(&mut CubicStyleBoard::new()?)?,
+    };
+
+    let header = RomHeader::from_bytes_lenient(&rom)?;
+
+    let actual_header_checksum = header_checksum(&rom);
+    let actual_global_checksum = file.as_ref().map(|_| global_checksum(&rom));
+    let logo_mismatches = header.logo_mismatches().len();
+    let mapper = Mapper::from_header(&header).ok();
+    // 新ライセンシーコードは旧ライセンシーコードが0x33のときだけ使われる
+    let new_licensee_code = if header.old_licensee_code == USE_NEW_LICENSEE_CODE {
+        Some(String::from_utf8_lossy(&header.new_licensee_code).to_string())
+    } else {
+        None
+    };
+    let rom_size = header.rom_size.bytes();
+    let ram_size = header.ram_size.bytes();
+
+    if json {
+        let info = json!({
+            "title": header.title(),
+            "manufacturer_code": header.manufacturer_code(),
+            "cgb_support": format!("{:?}", header.cgb_support),
+            "sgb_flag": header.sgb_flag.code(),
+            "sgb_support": header.sgb_flag == SgbFlag::Supported,
+            "cartridge_type": header.raw_mbc_type,
+            "mapper": mapper.map(|mapper| format!("{:?}", mapper)),
+            "rom_size": rom_size,
+            "rom_banks": rom_size.map(|size| size / 0x4000),
+            "ram_size": ram_size,
+            "ram_banks": ram_size.map(|size| size / 0x2000),
+            "destination_code": header.destination_code.code(),
+            "old_licensee_code": header.old_licensee_code,
+            "new_licensee_code": new_licensee_code,
+            "publisher": header.publisher(),
+            "version": header.mask_rom_version_number,
+            "header_checksum": {
+                "expected": header.header_checksum,
+                "actual": actual_header_checksum,
+                "ok": header.header_checksum == actual_header_checksum,
+            },
+            "global_checksum": {
+                "expected": header.expected_global_checksum(),
+                "actual": actual_global_checksum,
+                "ok": actual_global_checksum.map(|actual| actual == header.expected_global_checksum()),
+            },
+            "logo": {
+                "ok": logo_mismatches == 0,
+                "mismatches": logo_mismatches,
+            },
+        });
+
+        println!("{}", serde_json::to_string_pretty(&info)?);
+
+        return Ok(());
+    }
+
+    let ok = |ok: bool| if ok { "OK" } else { "NG" };
+
+    println!("タイトル: {}", header.title());
+    println!(
+        "メーカーコード: {}",
+        header
+            .manufacturer_code()
+            .unwrap_or_else(|| "-".to_string())
+    );
+    println!("CGB: {}", cgb_support_name(header.cgb_support));
+    println!(
+        "SGB: {}",
+        match header.sgb_flag {
+            SgbFlag::Supported => "対応".to_string(),
+            SgbFlag::Unsupported => "非対応".to_string(),
+            SgbFlag::Unknown(code) => format!("不明 ({:02X})", code),
+        }
+    );
+    println!(
+        "カートリッジタイプ: {:02X} ({})",
+        header.raw_mbc_type,
+        match mapper {
+            Some(mapper) => format!("{:?}", mapper),
+            None => "未対応".to_string(),
+        }
+    );
+    println!(
+        "ROMサイズ: {}",
+        match rom_size {
+            Some(size) => format!("{} ({}バンク)", HumanBytes(size as u64), size / 0x4000),
+            None => format!("不明 ({:02X})", header.rom_size.code().unwrap_or_default()),
+        }
+    );
+    println!(
+        "RAMサイズ: {}",
+        match ram_size {
+            Some(size) => format!("{} ({}バンク)", HumanBytes(size as u64), size / 0x2000),
+            None => format!("不明 ({:02X})", header.ram_size.code().unwrap_or_default()),
+        }
+    );
+    println!(
+        "仕向地: {}",
+        match header.destination_code {
+            DestinationCode::Japanese => "日本".to_string(),
+            DestinationCode::NonJapanese => "海外".to_string(),
+            DestinationCode::Unknown(code) => format!("不明 ({:02X})", code),
+        }
+    );
+    println!(
+        "発売元: {} (旧ライセンシーコード: {:02X}, 新ライセンシーコード: {})",
+        header.publisher().unwrap_or("不明"),
+        header.old_licensee_code,
+        new_licensee_code.as_deref().unwrap_or("-")
+    );
+    println!("バージョン: {}", header.mask_rom_version_number);
+    println!(
+        "ヘッダチェックサム: {} (期待値: {:02X}, 実際: {:02X})",
+        ok(header.header_checksum == actual_header_checksum),
+        header.header_checksum,
+        actual_header_checksum
+    );
+
+    match actual_global_checksum {
+        Some(actual) => println!(
+            "グローバルチェックサム: {} (期待値: {:04X}, 実際: {:04X})",
+            ok(actual == header.expected_global_checksum()),
+            header.expected_global_checksum(),
+            actual
+        ),
+        None => println!(
+            "グローバルチェックサム: {:04X} (未検証)",
+            header.expected_global_checksum()
+        ),
+    }
+
+    if logo_mismatches == 0 {
+        println!("ロゴ: OK");
+    } else {
+        println!("ロゴ: NG ({}バイト不一致)", logo_mismatches);
+    }
 
     Ok(())
 }
@@ -87,9 +1178,50 @@ fn read_rom(output: String, repl: bool) -> Result<()> {
 fn main() {
     let opts: Opts = Opts::parse();
 
+    log::set_logger(&LOGGER)
+        .map(|()| log::set_max_level(LevelFilter::Warn))
+        .unwrap();
+
+    // 2回目のCtrl-Cでは待たずに終了する
+    // 2回目はボードを持つメインスレッドを待たずに終了するため、バスを解放できない
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            eprintln!("バスを解放せずに終了しました。カートリッジを抜く前に拡張ボードの電源を入れ直してください");
+            process::exit(130);
+        }
+    })
+    .unwrap();
+
     let result = match opts.subcmd {
-        SubCommand::Read(t) => read_rom(t.output, t.repl),
+        SubCommand::Read(t) => read_rom(t),
+        SubCommand::GbMemory(t) => read_gb_memory(t.output, t.sram, t.map, t.split),
+        SubCommand::Fix(t) => fix_rom(t.file, t.title, t.cartridge_type),
+        SubCommand::RestoreSram(t) => restore_sram(t.input),
+        SubCommand::Info(t) => print_info(t.file, t.json),
+        SubCommand::Verify(t) => verify_rom(
+            t.file,
+            ReaderOptions {
+                mapper: t.mapper,
+                rom_size: t.rom_size.map(|kib| kib * 1024),
+                detect_size: false,
+                fallback: false,
+            },
+        ),
     };
 
-    result.unwrap();
+    if let Err(e) = result {
+        if let Some(Error::Interrupted) = e.downcast_ref::<Error>() {
+            eprintln!("中断しました");
+
+            process::exit(130);
+        }
+
+        eprintln!("エラー: {:#}", e);
+
+        if let Some(Error::UnsupportedMapper(_)) = e.downcast_ref::<Error>() {
+            eprintln!("未対応のカートリッジタイプです。--mapper でマッパーを指定するか、--fallback で先頭32KiBだけ読み出してください。");
+        }
+
+        process::exit(1);
+    }
 }
@@ -1,49 +1,242 @@
 use crate::board::CubicStyleBoard;
-use crate::rom::{MbcType, RomHeader};
-use anyhow::Result;
-use std::io::{stdin, ErrorKind, Read, Seek, SeekFrom};
-use std::time::Duration;
-use std::{io, thread};
+use crate::error::{Error, Result};
+use crate::probe::{check_bank_switch, probe_rom_size};
+use crate::rom::{MbcType, RamSize, RomHeader, RomSize};
+use log::warn;
+use std::io;
+use std::io::{stdin, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+pub trait MbcReader: Read + Seek {
+    fn size(&self) -> usize;
+    fn status(&self) -> String;
+}
+
+impl<R: MbcReader + ?Sized> MbcReader for Box<R> {
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+
+    fn status(&self) -> String {
+        (**self).status()
+    }
+}
 
-pub trait MbcReader: Read {
+pub trait MbcWriter: Write {
     fn size(&self) -> usize;
     fn status(&self) -> String;
 }
 
-pub fn new_mbc_reader<'a>(
-    board: &'a mut CubicStyleBoard,
-) -> Result<(Box<dyn MbcReader + 'a>, RomHeader)> {
-    let header = {
-        let mut reader = RomHeaderReader::new(board);
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mapper {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc30,
+    Mbc5,
+    WisdomTree,
+    SachenMmc1,
+    SachenMmc2,
+    M161,
+}
 
-        RomHeader::from_reader(&mut reader)
-    }?;
+impl Mapper {
+    pub fn from_header(header: &RomHeader) -> Result<Self> {
+        // ヘッダを緩く解析した場合、未知のカートリッジタイプはRomOnlyのままになっている
+        if header.mbc_type as u8 != header.raw_mbc_type {
+            return Err(Error::UnsupportedMapper(header.raw_mbc_type));
+        }
 
-    Ok((
-        match header.mbc_type {
-            MbcType::RomOnly => Box::new(RomOnlyReader::new(board, header)),
-            MbcType::Mbc1 | MbcType::Mbc1Ram | MbcType::Mbc1RamBattery => {
-                Box::new(Mbc1Reader::new(board, header))
-            }
-            MbcType::Mbc2 | MbcType::Mbc2Battery => Box::new(Mbc2Reader::new(board, header)),
+        Ok(match header.mbc_type {
+            MbcType::RomOnly | MbcType::RomRam | MbcType::RomRamBattery => Mapper::RomOnly,
+            MbcType::Mbc1 | MbcType::Mbc1Ram | MbcType::Mbc1RamBattery => Mapper::Mbc1,
+            MbcType::Mbc2 | MbcType::Mbc2Battery => Mapper::Mbc2,
             MbcType::Mbc3
             | MbcType::Mbc3Ram
             | MbcType::Mbc3RamBattery
-            | MbcType::Mbc3TimerRamBattery => Box::new(Mbc3Reader::new(board, header)),
+            | MbcType::Mbc3TimerRamBattery => {
+                if header.is_mbc30() {
+                    Mapper::Mbc30
+                } else {
+                    Mapper::Mbc3
+                }
+            }
             MbcType::Mbc5
             | MbcType::Mbc5Ram
             | MbcType::Mbc5RamBattery
             | MbcType::Mbc5Rumble
             | MbcType::Mbc5RumbleRam
-            | MbcType::Mbc5RumbleRamBattery => Box::new(Mbc5Reader::new(board, header)),
-            t => {
-                unimplemented!("unimplemented mbc: {:?}", t);
+            | MbcType::Mbc5RumbleRamBattery => Mapper::Mbc5,
+            _ => return Err(Error::UnsupportedMapper(header.raw_mbc_type)),
+        })
+    }
+}
+
+impl FromStr for Mapper {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "rom-only" => Mapper::RomOnly,
+            "mbc1" => Mapper::Mbc1,
+            "mbc2" => Mapper::Mbc2,
+            "mbc3" => Mapper::Mbc3,
+            "mbc30" => Mapper::Mbc30,
+            "mbc5" => Mapper::Mbc5,
+            "wisdom-tree" => Mapper::WisdomTree,
+            "sachen-mmc1" => Mapper::SachenMmc1,
+            "sachen-mmc2" => Mapper::SachenMmc2,
+            "m161" => Mapper::M161,
+            _ => return Err(Error::UnknownMapperName(s.to_string())),
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ReaderOptions {
+    // ヘッダのカートリッジタイプを無視して使用するマッパー
+    pub mapper: Option<Mapper>,
+    // ヘッダのROMサイズを無視して読み出すサイズ
+    pub rom_size: Option<usize>,
+    // ヘッダのROMサイズが有効な場合も、バンクのミラーから検出したサイズで読み出す
+    pub detect_size: bool,
+    // 未対応のマッパーでも先頭32KiBだけ読み出す
+    pub fallback: bool,
+}
+
+pub fn new_mbc_reader<'a>(
+    board: &'a mut CubicStyleBoard,
+    options: ReaderOptions,
+) -> Result<(Box<dyn MbcReader + 'a>, RomHeader)> {
+    let mut header = {
+        let mut reader = RomHeaderReader::new(board);
+
+        // 非正規カートリッジはヘッダが壊れていることが多いため、マッパー指定時は検証を緩める
+        // フォールバック時は未対応のMBCの種類だけを許容し、チェックサムは検証する
+        if options.mapper.is_some() {
+            RomHeader::from_reader_lenient(&mut reader)
+        } else if options.fallback {
+            RomHeader::from_reader_any_mapper(&mut reader)
+        } else {
+            RomHeader::from_reader(&mut reader)
+        }
+    }?;
+
+    let mut mapper = match options.mapper {
+        Some(mapper) => mapper,
+        None => match Mapper::from_header(&header) {
+            Ok(mapper) => mapper,
+            // バンク0はどのマッパーでも0x0000-0x3FFFに見えるため、RomOnlyとして読み出す
+            Err(Error::UnsupportedMapper(t)) if options.fallback => {
+                warn!(
+                    "unsupported mapper {:#04X}, reading the first 32 KiB as ROM only",
+                    t
+                );
+
+                header.rom_size = RomSize::Bytes(0x8000);
+
+                Mapper::RomOnly
+            }
+            Err(e) => return Err(e),
+        },
+    };
+
+    check_bank_switch(board, mapper)?;
+
+    if let Some(rom_size) = options.rom_size {
+        header.rom_size = RomSize::Bytes(rom_size);
+    } else if let Some(rom_size) = probe_rom_size(board, mapper)? {
+        // 接触不良でミラーを見誤ると、切り詰めたりミラーで水増ししたりしてしまう
+        // ヘッダのサイズが有効なら警告だけにして、指定された場合か不明な場合だけ検出したサイズを使う
+        let use_detected = options.detect_size || header.rom_size.bytes().is_none();
+
+        if Some(rom_size) != header.rom_size.bytes() {
+            warn!(
+                "ROM size mismatch header: {:?}, detected: {:#X}, using the {} size",
+                header.rom_size,
+                rom_size,
+                if use_detected { "detected" } else { "header" }
+            );
+        }
+
+        if use_detected {
+            // 2MiBを超えるMBC3はMBC30として読み出す
+            if mapper == Mapper::Mbc3 && rom_size > 2 * 1024 * 1024 {
+                mapper = Mapper::Mbc30;
             }
+
+            header.rom_size = RomSize::Bytes(rom_size);
+        }
+    }
+
+    // 不明なROMサイズのまま読み出すと空のダンプになってしまう
+    if let RomSize::Unknown(code) = header.rom_size {
+        return Err(Error::UnknownRomSize(code));
+    }
+
+    Ok((
+        match mapper {
+            Mapper::RomOnly => Box::new(RomOnlyReader::new(board, header)),
+            Mapper::Mbc1 => Box::new(Mbc1Reader::new(board, header)),
+            Mapper::Mbc2 => Box::new(Mbc2Reader::new(board, header)),
+            Mapper::Mbc3 => Box::new(Mbc3Reader::new(board, header, false)),
+            Mapper::Mbc30 => Box::new(Mbc3Reader::new(board, header, true)),
+            Mapper::Mbc5 => Box::new(Mbc5Reader::new(board, header)),
+            Mapper::WisdomTree => Box::new(WisdomTreeReader::new(board, header)),
+            Mapper::SachenMmc1 => Box::new(SachenReader::new(board, header, false)),
+            Mapper::SachenMmc2 => Box::new(SachenReader::new(board, header, true)),
+            Mapper::M161 => Box::new(M161Reader::new(board, header)),
         },
         header,
     ))
 }
 
+// SRAMのサイズを補正したヘッダと、RAMバンクレジスタのマスクを返す
+fn ram_layout(header: RomHeader) -> Result<(RomHeader, u8)> {
+    Ok(match header.mbc_type {
+        MbcType::Mbc3Ram | MbcType::Mbc3RamBattery | MbcType::Mbc3TimerRamBattery => {
+            // MBC30は0x00-0x07の8バンク、MBC3は0x00-0x03の4バンク
+            (header, 0b00000111)
+        }
+        MbcType::RomRam | MbcType::RomRamBattery => {
+            // マッパーが無いため、0xA000-0xBFFFの8KiBがそのまま見える
+            // RAM有効化やバンク選択の書き込みはROM領域への書き込みとして無視される
+            (
+                RomHeader {
+                    ram_size: RamSize::Bytes(0x2000),
+                    ..header
+                },
+                0,
+            )
+        }
+        t => return Err(Error::UnsupportedSram(t)),
+    })
+}
+
+// SRAMの読み書きに対応したカートリッジタイプかどうかを、ボードに触れずに確認する
+pub fn check_ram_support(header: &RomHeader) -> Result<()> {
+    ram_layout(*header).map(|_| ())
+}
+
+pub fn new_ram_reader<'a>(
+    board: &'a mut CubicStyleBoard,
+    header: RomHeader,
+) -> Result<Box<dyn MbcReader + 'a>> {
+    let (header, bank_mask) = ram_layout(header)?;
+
+    Ok(Box::new(RamReader::new(board, header, bank_mask)))
+}
+
+pub fn new_ram_writer<'a>(
+    board: &'a mut CubicStyleBoard,
+    header: RomHeader,
+) -> Result<Box<dyn MbcWriter + 'a>> {
+    let (header, bank_mask) = ram_layout(header)?;
+
+    Ok(Box::new(RamWriter::new(board, header, bank_mask)))
+}
+
 pub fn new_repl_mbc_reader<'a>(
     board: &'a mut CubicStyleBoard,
 ) -> Result<(Box<dyn MbcReader + 'a>, RomHeader)> {
@@ -68,7 +261,7 @@ impl<'a> RomHeaderReader<'a> {
     }
 
     fn is_valid_addr(&self, addr: i64) -> bool {
-        0 <= addr && addr < 0x150
+        (0..0x150).contains(&addr)
     }
 }
 
@@ -136,7 +329,7 @@ impl<'a> RomOnlyReader<'a> {
     }
 
     fn is_valid_addr(&self, addr: i64) -> bool {
-        0 <= addr && addr < 0x8000
+        (0..0x8000).contains(&addr)
     }
 }
 
@@ -164,6 +357,14 @@ impl<'a> Read for RomOnlyReader<'a> {
     }
 }
 
+impl<'a> Seek for RomOnlyReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.addr = seek_addr(pos, self.addr as u32, self.size())? as u16;
+
+        Ok(self.addr as u64)
+    }
+}
+
 pub struct Mbc1Reader<'a> {
     board: &'a mut CubicStyleBoard,
 
@@ -187,7 +388,7 @@ impl<'a> Mbc1Reader<'a> {
         Self {
             board,
             addr: 0,
-            size: header.rom_size,
+            size: header.rom_size.bytes().unwrap_or_default(),
             bank: 0,
         }
     }
@@ -200,6 +401,21 @@ impl<'a> Mbc1Reader<'a> {
         }) as u16
     }
 
+    // 0x20/0x40/0x60は0x4000-0x7FFFに割り当てられないため、次のバンクを読む
+    fn bank_for(addr: u32) -> u8 {
+        let mut bank = 0;
+
+        for _ in 0..addr / 0x4000 {
+            bank += 1;
+
+            if let 0x20 | 0x40 | 0x60 = bank {
+                bank += 1;
+            }
+        }
+
+        bank
+    }
+
     fn select_rom_bank(&mut self) -> Result<()> {
         let bank_low = self.bank & 0b00011111;
         let bank_high = (self.bank >> 5) & 0b00000011;
@@ -227,15 +443,8 @@ impl<'a> Read for Mbc1Reader<'a> {
                 break;
             }
 
-            if self.addr != 0 && self.addr % 0x4000 == 0 {
-                self.bank += 1;
-
-                match self.bank {
-                    0x20 | 0x40 | 0x60 => {
-                        self.bank += 1;
-                    }
-                    _ => {}
-                }
+            if self.addr != 0 && self.addr.is_multiple_of(0x4000) {
+                self.bank = Self::bank_for(self.addr);
 
                 self.select_rom_bank()
                     .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
@@ -255,6 +464,22 @@ impl<'a> Read for Mbc1Reader<'a> {
     }
 }
 
+impl<'a> Seek for Mbc1Reader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.addr = seek_addr(pos, self.addr, self.size)?;
+
+        // 別のバンクから戻ってきた場合に備えて、シーク先のバンクを選択し直す
+        if self.addr >= 0x4000 && self.is_valid_addr(self.addr as i64) {
+            self.bank = Self::bank_for(self.addr);
+
+            self.select_rom_bank()
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+        }
+
+        Ok(self.addr as u64)
+    }
+}
+
 pub struct Mbc2Reader<'a> {
     board: &'a mut CubicStyleBoard,
 
@@ -278,7 +503,7 @@ impl<'a> Mbc2Reader<'a> {
         Self {
             board,
             addr: 0,
-            size: header.rom_size,
+            size: header.rom_size.bytes().unwrap_or_default(),
             bank: 0,
         }
     }
@@ -314,8 +539,8 @@ impl<'a> Read for Mbc2Reader<'a> {
                 break;
             }
 
-            if self.addr != 0 && self.addr % 0x4000 == 0 {
-                self.bank += 1;
+            if self.addr != 0 && self.addr.is_multiple_of(0x4000) {
+                self.bank = (self.addr / 0x4000) as u8;
 
                 self.select_rom_bank()
                     .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
@@ -335,12 +560,29 @@ impl<'a> Read for Mbc2Reader<'a> {
     }
 }
 
+impl<'a> Seek for Mbc2Reader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.addr = seek_addr(pos, self.addr, self.size)?;
+
+        // 別のバンクから戻ってきた場合に備えて、シーク先のバンクを選択し直す
+        if self.addr >= 0x4000 && self.is_valid_addr(self.addr as i64) {
+            self.bank = (self.addr / 0x4000) as u8;
+
+            self.select_rom_bank()
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+        }
+
+        Ok(self.addr as u64)
+    }
+}
+
 pub struct Mbc3Reader<'a> {
     board: &'a mut CubicStyleBoard,
 
     addr: u32,
     size: usize,
     bank: u8,
+    bank_mask: u8,
 }
 
 impl<'a> MbcReader for Mbc3Reader<'a> {
@@ -354,12 +596,14 @@ impl<'a> MbcReader for Mbc3Reader<'a> {
 }
 
 impl<'a> Mbc3Reader<'a> {
-    pub fn new(board: &'a mut CubicStyleBoard, header: RomHeader) -> Self {
+    pub fn new(board: &'a mut CubicStyleBoard, header: RomHeader, mbc30: bool) -> Self {
         Self {
             board,
             addr: 0,
-            size: header.rom_size,
+            size: header.rom_size.bytes().unwrap_or_default(),
             bank: 0,
+            // MBC30はROMバンクレジスタが8bit
+            bank_mask: if mbc30 { 0b11111111 } else { 0b01111111 },
         }
     }
 
@@ -372,8 +616,10 @@ impl<'a> Mbc3Reader<'a> {
     }
 
     fn select_rom_bank(&mut self) -> Result<()> {
+        let bank = self.bank & self.bank_mask;
+
         self.board.set_addr(0x2000);
-        self.board.write_byte(self.bank)?;
+        self.board.write_byte(bank)?;
 
         Ok(())
     }
@@ -392,8 +638,8 @@ impl<'a> Read for Mbc3Reader<'a> {
                 break;
             }
 
-            if self.addr != 0 && self.addr % 0x4000 == 0 {
-                self.bank += 1;
+            if self.addr != 0 && self.addr.is_multiple_of(0x4000) {
+                self.bank = (self.addr / 0x4000) as u8;
 
                 self.select_rom_bank()
                     .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
@@ -413,51 +659,70 @@ impl<'a> Read for Mbc3Reader<'a> {
     }
 }
 
-pub struct Mbc5Reader<'a> {
+impl<'a> Seek for Mbc3Reader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.addr = seek_addr(pos, self.addr, self.size)?;
+
+        // 別のバンクから戻ってきた場合に備えて、シーク先のバンクを選択し直す
+        if self.addr >= 0x4000 && self.is_valid_addr(self.addr as i64) {
+            self.bank = (self.addr / 0x4000) as u8;
+
+            self.select_rom_bank()
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+        }
+
+        Ok(self.addr as u64)
+    }
+}
+
+pub struct RamReader<'a> {
     board: &'a mut CubicStyleBoard,
 
     addr: u32,
     size: usize,
-    bank: u16,
+    bank: u8,
+    bank_mask: u8,
+    enabled: bool,
 }
 
-impl<'a> MbcReader for Mbc5Reader<'a> {
+impl<'a> MbcReader for RamReader<'a> {
     fn size(&self) -> usize {
         self.size
     }
 
     fn status(&self) -> String {
-        format!("BANK#{} {:#04X}", self.bank, self.cur_addr())
+        format!("RAM BANK#{} {:#04X}", self.bank, self.cur_addr())
     }
 }
 
-impl<'a> Mbc5Reader<'a> {
-    pub fn new(board: &'a mut CubicStyleBoard, header: RomHeader) -> Self {
+impl<'a> RamReader<'a> {
+    pub fn new(board: &'a mut CubicStyleBoard, header: RomHeader, bank_mask: u8) -> Self {
         Self {
             board,
             addr: 0,
-            size: header.rom_size,
+            size: header.ram_size.bytes().unwrap_or_default(),
             bank: 0,
+            bank_mask,
+            enabled: false,
         }
     }
 
     fn cur_addr(&self) -> u16 {
-        (if self.addr >= 0x4000 {
-            self.addr % 0x4000 + 0x4000
-        } else {
-            self.addr
-        }) as u16
+        (self.addr % 0x2000 + 0xA000) as u16
     }
 
-    fn select_rom_bank(&mut self) -> Result<()> {
-        let bank_low = (self.bank & 0xFF) as u8;
-        let bank_high = ((self.bank >> 8) & 0b00000001) as u8;
+    fn set_ram_enabled(&mut self, enabled: bool) -> Result<()> {
+        self.board.set_addr(0x0000);
+        self.board.write_byte(if enabled { 0x0A } else { 0x00 })?;
 
-        self.board.set_addr(0x2000);
-        self.board.write_byte(bank_low)?;
+        self.enabled = enabled;
 
-        self.board.set_addr(0x3000);
-        self.board.write_byte(bank_high)?;
+        Ok(())
+    }
+
+    fn select_ram_bank(&mut self) -> Result<()> {
+        self.board.set_addr(0x4000);
+        self.board.write_byte(self.bank & self.bank_mask)?;
 
         Ok(())
     }
@@ -467,7 +732,7 @@ impl<'a> Mbc5Reader<'a> {
     }
 }
 
-impl<'a> Read for Mbc5Reader<'a> {
+impl<'a> Read for RamReader<'a> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let mut n = 0;
 
@@ -476,10 +741,15 @@ impl<'a> Read for Mbc5Reader<'a> {
                 break;
             }
 
-            if self.addr != 0 && self.addr % 0x4000 == 0 {
-                self.bank += 1;
+            if self.addr.is_multiple_of(0x2000) {
+                if !self.enabled {
+                    self.set_ram_enabled(true)
+                        .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+                }
 
-                self.select_rom_bank()
+                self.bank = (self.addr / 0x2000) as u8;
+
+                self.select_ram_bank()
                     .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
             }
 
@@ -493,89 +763,726 @@ impl<'a> Read for Mbc5Reader<'a> {
             n += 1;
         }
 
+        // 読み終えたらセーブデータ保護のためRAMを無効化する
+        if self.enabled && !self.is_valid_addr(self.addr as i64) {
+            self.set_ram_enabled(false)
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+        }
+
         Ok(n)
     }
 }
 
-pub struct ReplReader<'a> {
+impl<'a> Seek for RamReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.addr = seek_addr(pos, self.addr, self.size)?;
+
+        if self.is_valid_addr(self.addr as i64) {
+            if !self.enabled {
+                self.set_ram_enabled(true)
+                    .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+            }
+
+            self.bank = (self.addr / 0x2000) as u8;
+
+            self.select_ram_bank()
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+        }
+
+        Ok(self.addr as u64)
+    }
+}
+
+impl<'a> Drop for RamReader<'a> {
+    fn drop(&mut self) {
+        if self.enabled {
+            let _ = self.set_ram_enabled(false);
+        }
+    }
+}
+
+pub struct RamWriter<'a> {
     board: &'a mut CubicStyleBoard,
 
     addr: u32,
     size: usize,
+    bank: u8,
+    bank_mask: u8,
+    enabled: bool,
 }
 
-impl<'a> MbcReader for ReplReader<'a> {
+impl<'a> MbcWriter for RamWriter<'a> {
     fn size(&self) -> usize {
         self.size
     }
 
     fn status(&self) -> String {
-        format!("MANUAL {:#04X}", self.addr)
+        format!("RAM BANK#{} {:#04X}", self.bank, self.cur_addr())
     }
 }
 
-impl<'a> ReplReader<'a> {
-    pub fn new(board: &'a mut CubicStyleBoard, header: RomHeader) -> Self {
+impl<'a> RamWriter<'a> {
+    pub fn new(board: &'a mut CubicStyleBoard, header: RomHeader, bank_mask: u8) -> Self {
         Self {
             board,
             addr: 0,
-            size: header.rom_size,
+            size: header.ram_size.bytes().unwrap_or_default(),
+            bank: 0,
+            bank_mask,
+            enabled: false,
         }
     }
+
+    fn cur_addr(&self) -> u16 {
+        (self.addr % 0x2000 + 0xA000) as u16
+    }
+
+    fn set_ram_enabled(&mut self, enabled: bool) -> Result<()> {
+        self.board.set_addr(0x0000);
+        self.board.write_byte(if enabled { 0x0A } else { 0x00 })?;
+
+        self.enabled = enabled;
+
+        Ok(())
+    }
+
+    fn select_ram_bank(&mut self) -> Result<()> {
+        self.board.set_addr(0x4000);
+        self.board.write_byte(self.bank & self.bank_mask)?;
+
+        Ok(())
+    }
+
+    fn is_valid_addr(&self, addr: i64) -> bool {
+        0 <= addr && addr < self.size as i64
+    }
 }
 
-impl<'a> Read for ReplReader<'a> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        loop {
-            let mut cmd = String::new();
-            let mut addr = String::new();
+impl<'a> Write for RamWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut n = 0;
 
-            println!("");
+        for data in buf.iter() {
+            if !self.is_valid_addr(self.addr as i64) {
+                break;
+            }
 
-            println!("CMD?");
-            stdin().read_line(&mut cmd)?;
+            if self.addr.is_multiple_of(0x2000) {
+                if !self.enabled {
+                    self.set_ram_enabled(true)
+                        .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+                }
 
-            println!("ADDR?");
-            stdin().read_line(&mut addr)?;
+                self.bank = (self.addr / 0x2000) as u8;
+
+                self.select_ram_bank()
+                    .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+            }
 
-            let addr = u16::from_str_radix(&(addr.trim()), 16)
+            self.board.set_addr(self.cur_addr());
+            self.board
+                .write_byte(*data)
                 .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
 
-            match &(cmd.trim())[..] {
-                "R" => {
-                    self.board.set_addr(addr);
+            self.addr += 1;
+            n += 1;
+        }
 
-                    let data = self
-                        .board
-                        .read_byte()
-                        .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+        // 書き終えたらセーブデータ保護のためRAMを無効化する
+        if self.enabled && !self.is_valid_addr(self.addr as i64) {
+            self.set_ram_enabled(false)
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+        }
 
-                    println!("READ DATA: 0x{:04X} = {:02X}", addr, data);
-                }
-                "W" => {
-                    self.board.set_addr(addr);
+        Ok(n)
+    }
 
-                    let mut value = String::new();
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
-                    println!("VAL?");
-                    stdin().read_line(&mut value)?;
+impl<'a> Drop for RamWriter<'a> {
+    fn drop(&mut self) {
+        if self.enabled {
+            let _ = self.set_ram_enabled(false);
+        }
+    }
+}
 
-                    let val = u8::from_str_radix(&(value.trim()), 16)
-                        .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+pub struct Mbc5Reader<'a> {
+    board: &'a mut CubicStyleBoard,
 
-                    self.board
-                        .write_byte(val)
-                        .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+    addr: u32,
+    size: usize,
+    bank: u16,
+}
 
-                    println!("WRITE DATA: 0x{:04X} = {:02X}", addr, val);
-                }
-                "Q" => {
-                    return Ok(self.size());
-                }
-                _ => {
-                    println!("INVALID");
-                }
-            }
+impl<'a> MbcReader for Mbc5Reader<'a> {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn status(&self) -> String {
+        format!("BANK#{} {:#04X}", self.bank, self.cur_addr())
+    }
+}
+
+impl<'a> Mbc5Reader<'a> {
+    pub fn new(board: &'a mut CubicStyleBoard, header: RomHeader) -> Self {
+        Self {
+            board,
+            addr: 0,
+            size: header.rom_size.bytes().unwrap_or_default(),
+            bank: 0,
+        }
+    }
+
+    fn cur_addr(&self) -> u16 {
+        (if self.addr >= 0x4000 {
+            self.addr % 0x4000 + 0x4000
+        } else {
+            self.addr
+        }) as u16
+    }
+
+    fn select_rom_bank(&mut self) -> Result<()> {
+        let bank_low = (self.bank & 0xFF) as u8;
+        let bank_high = ((self.bank >> 8) & 0b00000001) as u8;
+
+        self.board.set_addr(0x2000);
+        self.board.write_byte(bank_low)?;
+
+        self.board.set_addr(0x3000);
+        self.board.write_byte(bank_high)?;
+
+        Ok(())
+    }
+
+    fn is_valid_addr(&self, addr: i64) -> bool {
+        0 <= addr && addr < self.size as i64
+    }
+}
+
+impl<'a> Read for Mbc5Reader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+
+        for data in buf.iter_mut() {
+            if !self.is_valid_addr(self.addr as i64) {
+                break;
+            }
+
+            if self.addr != 0 && self.addr.is_multiple_of(0x4000) {
+                self.bank = (self.addr / 0x4000) as u16;
+
+                self.select_rom_bank()
+                    .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+            }
+
+            self.board.set_addr(self.cur_addr());
+            *data = self
+                .board
+                .read_byte()
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+
+            self.addr += 1;
+            n += 1;
+        }
+
+        Ok(n)
+    }
+}
+
+impl<'a> Seek for Mbc5Reader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.addr = seek_addr(pos, self.addr, self.size)?;
+
+        // 別のバンクから戻ってきた場合に備えて、シーク先のバンクを選択し直す
+        if self.addr >= 0x4000 && self.is_valid_addr(self.addr as i64) {
+            self.bank = (self.addr / 0x4000) as u16;
+
+            self.select_rom_bank()
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+        }
+
+        Ok(self.addr as u64)
+    }
+}
+
+pub struct WisdomTreeReader<'a> {
+    board: &'a mut CubicStyleBoard,
+
+    addr: u32,
+    size: usize,
+    bank: u8,
+}
+
+impl<'a> MbcReader for WisdomTreeReader<'a> {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn status(&self) -> String {
+        format!("BANK#{} {:#04X}", self.bank, self.cur_addr())
+    }
+}
+
+impl<'a> WisdomTreeReader<'a> {
+    pub fn new(board: &'a mut CubicStyleBoard, header: RomHeader) -> Self {
+        Self {
+            board,
+            addr: 0,
+            size: header.rom_size.bytes().unwrap_or_default(),
+            bank: 0,
+        }
+    }
+
+    fn cur_addr(&self) -> u16 {
+        (self.addr % 0x8000) as u16
+    }
+
+    fn select_rom_bank(&mut self) -> Result<()> {
+        // 書き込み先アドレスの下位8bitがそのまま32KiB単位のバンク番号になる（値は無視される）
+        self.board.set_addr(self.bank as u16);
+        self.board.write_byte(self.bank)?;
+
+        Ok(())
+    }
+
+    fn is_valid_addr(&self, addr: i64) -> bool {
+        0 <= addr && addr < self.size as i64
+    }
+}
+
+impl<'a> Read for WisdomTreeReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+
+        for data in buf.iter_mut() {
+            if !self.is_valid_addr(self.addr as i64) {
+                break;
+            }
+
+            if self.addr.is_multiple_of(0x8000) {
+                self.bank = (self.addr / 0x8000) as u8;
+
+                self.select_rom_bank()
+                    .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+            }
+
+            self.board.set_addr(self.cur_addr());
+            *data = self
+                .board
+                .read_byte()
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+
+            self.addr += 1;
+            n += 1;
+        }
+
+        Ok(n)
+    }
+}
+
+impl<'a> Seek for WisdomTreeReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.addr = seek_addr(pos, self.addr, self.size)?;
+
+        if self.is_valid_addr(self.addr as i64) {
+            self.bank = (self.addr / 0x8000) as u8;
+
+            self.select_rom_bank()
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+        }
+
+        Ok(self.addr as u64)
+    }
+}
+
+// 起動時のロゴ読み出し中にA15が0x31回変化するとスクランブルが解除される
+const SACHEN_UNLOCK_COUNT: usize = 0x31;
+
+pub struct SachenReader<'a> {
+    board: &'a mut CubicStyleBoard,
+
+    addr: u32,
+    size: usize,
+    bank: u8,
+    mmc2: bool,
+}
+
+impl<'a> MbcReader for SachenReader<'a> {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn status(&self) -> String {
+        format!("BANK#{} {:#04X}", self.bank, self.cur_addr())
+    }
+}
+
+impl<'a> SachenReader<'a> {
+    pub fn new(board: &'a mut CubicStyleBoard, header: RomHeader, mmc2: bool) -> Self {
+        Self {
+            board,
+            addr: 0,
+            size: header.rom_size.bytes().unwrap_or_default(),
+            bank: 0,
+            mmc2,
+        }
+    }
+
+    fn cur_addr(&self) -> u16 {
+        (if self.addr >= 0x4000 {
+            self.addr % 0x4000 + 0x4000
+        } else {
+            self.addr
+        }) as u16
+    }
+
+    fn unlock(&mut self) -> Result<()> {
+        // MMC2はDMGロック → CGBロック → 解除と2段階でロックが外れる
+        let count = if self.mmc2 {
+            SACHEN_UNLOCK_COUNT * 2
+        } else {
+            SACHEN_UNLOCK_COUNT
+        };
+
+        for _ in 0..count {
+            self.board.set_addr(0x8000);
+            self.board.set_addr(0x0000);
+        }
+
+        // バンク番号の上位2bitが立っている間だけベースバンクとマスクを書き換えられる
+        self.board.set_addr(0x2000);
+        self.board.write_byte(0x30)?;
+
+        self.board.set_addr(0x0000);
+        self.board.write_byte(0x00)?;
+
+        self.board.set_addr(0x4000);
+        self.board.write_byte(0x00)?;
+
+        Ok(())
+    }
+
+    fn select_rom_bank(&mut self) -> Result<()> {
+        self.board.set_addr(0x2000);
+        self.board.write_byte(self.bank)?;
+
+        Ok(())
+    }
+
+    fn is_valid_addr(&self, addr: i64) -> bool {
+        0 <= addr && addr < self.size as i64
+    }
+}
+
+impl<'a> Read for SachenReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+
+        for data in buf.iter_mut() {
+            if !self.is_valid_addr(self.addr as i64) {
+                break;
+            }
+
+            if self.addr == 0 {
+                self.unlock()
+                    .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+            }
+
+            if self.addr != 0 && self.addr.is_multiple_of(0x4000) {
+                self.bank = (self.addr / 0x4000) as u8;
+
+                self.select_rom_bank()
+                    .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+            }
+
+            self.board.set_addr(self.cur_addr());
+            *data = self
+                .board
+                .read_byte()
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+
+            self.addr += 1;
+            n += 1;
+        }
+
+        Ok(n)
+    }
+}
+
+impl<'a> Seek for SachenReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.addr = seek_addr(pos, self.addr, self.size)?;
+
+        // 別のバンクから戻ってきた場合に備えて、シーク先のバンクを選択し直す
+        if self.addr >= 0x4000 && self.is_valid_addr(self.addr as i64) {
+            self.bank = (self.addr / 0x4000) as u8;
+
+            self.select_rom_bank()
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+        }
+
+        Ok(self.addr as u64)
+    }
+}
+
+pub struct M161Reader<'a> {
+    board: &'a mut CubicStyleBoard,
+
+    addr: u32,
+    size: usize,
+    bank: u8,
+}
+
+impl<'a> MbcReader for M161Reader<'a> {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn status(&self) -> String {
+        format!("BANK#{} {:#04X}", self.bank, self.cur_addr())
+    }
+}
+
+impl<'a> M161Reader<'a> {
+    pub fn new(board: &'a mut CubicStyleBoard, header: RomHeader) -> Self {
+        Self {
+            board,
+            addr: 0,
+            size: header.rom_size.bytes().unwrap_or_default(),
+            bank: 0,
+        }
+    }
+
+    fn cur_addr(&self) -> u16 {
+        (self.addr % 0x8000) as u16
+    }
+
+    fn select_rom_bank(&mut self) -> Result<()> {
+        // バンクレジスタは一度書き込むとラッチされるため、リセットしてから書き込む
+        self.board.reset();
+
+        self.board.set_addr(0x4000);
+        self.board.write_byte(self.bank & 0b00000111)?;
+
+        Ok(())
+    }
+
+    fn is_valid_addr(&self, addr: i64) -> bool {
+        0 <= addr && addr < self.size as i64
+    }
+}
+
+impl<'a> Read for M161Reader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+
+        for data in buf.iter_mut() {
+            if !self.is_valid_addr(self.addr as i64) {
+                break;
+            }
+
+            if self.addr.is_multiple_of(0x8000) {
+                self.bank = (self.addr / 0x8000) as u8;
+
+                self.select_rom_bank()
+                    .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+            }
+
+            self.board.set_addr(self.cur_addr());
+            *data = self
+                .board
+                .read_byte()
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+
+            self.addr += 1;
+            n += 1;
+        }
+
+        Ok(n)
+    }
+}
+
+impl<'a> Seek for M161Reader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.addr = seek_addr(pos, self.addr, self.size)?;
+
+        if self.is_valid_addr(self.addr as i64) {
+            self.bank = (self.addr / 0x8000) as u8;
+
+            self.select_rom_bank()
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+        }
+
+        Ok(self.addr as u64)
+    }
+}
+
+pub struct ReplReader<'a> {
+    board: &'a mut CubicStyleBoard,
+
+    addr: u32,
+    size: usize,
+}
+
+impl<'a> MbcReader for ReplReader<'a> {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn status(&self) -> String {
+        format!("MANUAL {:#04X}", self.addr)
+    }
+}
+
+impl<'a> ReplReader<'a> {
+    pub fn new(board: &'a mut CubicStyleBoard, header: RomHeader) -> Self {
+        Self {
+            board,
+            addr: 0,
+            size: header.rom_size.bytes().unwrap_or_default(),
+        }
+    }
+}
+
+impl<'a> Read for ReplReader<'a> {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut cmd = String::new();
+            let mut addr = String::new();
+
+            println!();
+
+            println!("CMD?");
+            stdin().read_line(&mut cmd)?;
+
+            println!("ADDR?");
+            stdin().read_line(&mut addr)?;
+
+            let addr = u16::from_str_radix(addr.trim(), 16)
+                .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+
+            match cmd.trim() {
+                "R" => {
+                    self.board.set_addr(addr);
+
+                    let data = self
+                        .board
+                        .read_byte()
+                        .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+
+                    println!("READ DATA: 0x{:04X} = {:02X}", addr, data);
+                }
+                "W" => {
+                    self.board.set_addr(addr);
+
+                    let mut value = String::new();
+
+                    println!("VAL?");
+                    stdin().read_line(&mut value)?;
+
+                    let val = u8::from_str_radix(value.trim(), 16)
+                        .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+
+                    self.board
+                        .write_byte(val)
+                        .map_err(|e| io::Error::new(ErrorKind::BrokenPipe, e))?;
+
+                    println!("WRITE DATA: 0x{:04X} = {:02X}", addr, val);
+                }
+                "Q" => {
+                    return Ok(self.size());
+                }
+                _ => {
+                    println!("INVALID");
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Seek for ReplReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // 現在位置の取得だけを受け付ける
+        match pos {
+            SeekFrom::Current(0) => Ok(self.addr as u64),
+            _ => Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "manual reader is not seekable",
+            )),
+        }
+    }
+}
+
+// 0..=sizeの範囲でシーク先のアドレスを求める
+fn seek_addr(pos: SeekFrom, addr: u32, size: usize) -> io::Result<u32> {
+    let addr = match pos {
+        SeekFrom::Start(x) => x as i64,
+        SeekFrom::End(x) => size as i64 + x,
+        SeekFrom::Current(x) => addr as i64 + x,
+    };
+
+    if !(0..=size as i64).contains(&addr) {
+        return Err(io::Error::new(ErrorKind::AddrNotAvailable, "out of range"));
+    }
+
+    Ok(addr as u32)
+}
+
+// テスト用のリーダー
+#[cfg(test)]
+pub mod testing {
+    use super::MbcReader;
+    use std::collections::BTreeSet;
+    use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+    // メモリ上のROMを読み出すリーダー。flakyに指定したアドレスは最初の1回だけ壊れた値を返す
+    pub struct MemoryReader {
+        data: Cursor<Vec<u8>>,
+        flaky: BTreeSet<u64>,
+    }
+
+    impl MemoryReader {
+        pub fn new(data: Vec<u8>, flaky: &[u64]) -> Self {
+            Self {
+                data: Cursor::new(data),
+                flaky: flaky.iter().copied().collect(),
+            }
+        }
+    }
+
+    impl MbcReader for MemoryReader {
+        fn size(&self) -> usize {
+            self.data.get_ref().len()
+        }
+
+        fn status(&self) -> String {
+            String::new()
+        }
+    }
+
+    impl Read for MemoryReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let start = self.data.position();
+            let n = self.data.read(buf)?;
+
+            for (i, b) in buf[..n].iter_mut().enumerate() {
+                if self.flaky.remove(&(start + i as u64)) {
+                    *b ^= 0xFF;
+                }
+            }
+
+            Ok(n)
+        }
+    }
+
+    impl Seek for MemoryReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.data.seek(pos)
         }
     }
 }
@@ -0,0 +1,232 @@
+use crate::board::CubicStyleBoard;
+use crate::error::{Error, Result};
+use crate::mbc::Mapper;
+
+// バンクの比較は全バイトではなく、一定間隔でサンプリングしたバイト列で行う
+const SAMPLE_COUNT: u16 = 0x0100;
+const SAMPLE_STRIDE: u16 = 0x0040;
+
+// ミラーかどうかを判断するまでにバンクを比較する回数
+const MIRROR_READS: usize = 3;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Probe {
+    pub mapper: Mapper,
+    pub banks: usize,
+}
+
+impl Probe {
+    pub fn rom_size(&self) -> usize {
+        self.banks * 0x4000
+    }
+}
+
+// バンクレジスタに書き込み、0x4000-0x7FFFの内容が変化するかどうかでマッパーを推定する
+pub fn probe_mapper(board: &mut CubicStyleBoard) -> Result<Probe> {
+    board.reset();
+
+    let bank0 = sample(board, 0x0000)?;
+
+    // MBC1/3/5は0x2000への書き込みでバンクが切り替わるが、
+    // MBC2はA8が立っていないとRAM有効化レジスタとして扱われる
+    let mapper = if switches(board, 0x2000)? {
+        write(board, 0x2000, 0x00)?;
+
+        if sample(board, 0x4000)? == bank0 {
+            // バンク0を選択できるのはMBC5だけ
+            Mapper::Mbc5
+        } else {
+            write(board, 0x2000, 0x01)?;
+            let bank1 = sample(board, 0x4000)?;
+
+            // MBC1は下位5bitしか見ないため0x21はバンク1になる
+            // 32バンク以下のMBC3とは区別できないが、MBC1として読み出しても問題ない
+            write(board, 0x2000, 0x21)?;
+
+            if sample(board, 0x4000)? == bank1 {
+                Mapper::Mbc1
+            } else {
+                Mapper::Mbc3
+            }
+        }
+    } else if switches(board, 0x2100)? {
+        Mapper::Mbc2
+    } else {
+        board.reset();
+
+        return Ok(Probe {
+            mapper: Mapper::RomOnly,
+            banks: 2,
+        });
+    };
+
+    let banks = count_banks(board, mapper)?;
+
+    board.reset();
+
+    Ok(Probe {
+        mapper: if mapper == Mapper::Mbc3 && banks > 128 {
+            Mapper::Mbc30
+        } else {
+            mapper
+        },
+        banks,
+    })
+}
+
+// 2の累乗の境界でバンクのミラーを検出し、実際のROMサイズを求める
+pub fn probe_rom_size(board: &mut CubicStyleBoard, mapper: Mapper) -> Result<Option<usize>> {
+    let size = match mapper {
+        Mapper::RomOnly => Some(0x8000),
+        Mapper::Mbc1 | Mapper::Mbc2 | Mapper::Mbc3 | Mapper::Mbc30 | Mapper::Mbc5 => {
+            Some(count_banks(board, mapper)? * 0x4000)
+        }
+        _ => None,
+    };
+
+    board.reset();
+
+    Ok(size)
+}
+
+// 3.3Vで動作しないカートリッジは、バンクを切り替えても0x4000-0x7FFFにバンク0が見え続ける
+pub fn check_bank_switch(board: &mut CubicStyleBoard, mapper: Mapper) -> Result<()> {
+    match mapper {
+        Mapper::Mbc1 | Mapper::Mbc2 | Mapper::Mbc3 | Mapper::Mbc30 | Mapper::Mbc5 => {}
+        _ => return Ok(()),
+    }
+
+    let bank0 = sample(board, 0x0000)?;
+
+    select_bank(board, mapper, 1)?;
+    let bank1 = sample(board, 0x4000)?;
+
+    board.reset();
+
+    if bank0 == bank1 {
+        return Err(Error::BankMirror);
+    }
+
+    Ok(())
+}
+
+fn count_banks(board: &mut CubicStyleBoard, mapper: Mapper) -> Result<usize> {
+    let max_banks = match mapper {
+        Mapper::Mbc1 => 128,
+        Mapper::Mbc2 => 16,
+        // MBC3はバンクレジスタが7bitのため、MBC30でなければ128バンクでミラーになる
+        Mapper::Mbc3 | Mapper::Mbc30 => 256,
+        _ => 512,
+    };
+
+    let mut banks = 2;
+
+    while banks < max_banks {
+        if is_mirror(board, mapper, banks)? {
+            break;
+        }
+
+        banks *= 2;
+    }
+
+    Ok(banks)
+}
+
+// バンク数Nのとき、バンクN+kはバンクkのミラーになる
+fn is_mirror(board: &mut CubicStyleBoard, mapper: Mapper, banks: usize) -> Result<bool> {
+    if !same_bank(board, mapper, 1, banks + 1, read_bank)? {
+        return Ok(false);
+    }
+
+    // 0xFFで埋められた空きバンク同士も一致するため、バンク1だけではミラーと判断できない
+    // 残りのバンクもサンプリングして比較し、全て一致した場合だけミラーとみなす
+    for bank in 2..banks {
+        if !same_bank(board, mapper, bank, banks + bank, |board| {
+            sample(board, 0x4000)
+        })? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+// 接触不良で一度だけ読み違えることがあるため、一致しなければ読み直して確かめる
+fn same_bank(
+    board: &mut CubicStyleBoard,
+    mapper: Mapper,
+    a: usize,
+    b: usize,
+    read: impl Fn(&mut CubicStyleBoard) -> Result<Vec<u8>>,
+) -> Result<bool> {
+    for _ in 0..MIRROR_READS {
+        select_bank(board, mapper, a)?;
+        let data = read(board)?;
+
+        select_bank(board, mapper, b)?;
+
+        if read(board)? == data {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn read_bank(board: &mut CubicStyleBoard) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(0x4000);
+
+    for addr in 0x4000..0x8000 {
+        board.set_addr(addr);
+        data.push(board.read_byte()?);
+    }
+
+    Ok(data)
+}
+
+fn switches(board: &mut CubicStyleBoard, addr: u16) -> Result<bool> {
+    write(board, addr, 0x01)?;
+    let bank1 = sample(board, 0x4000)?;
+
+    write(board, addr, 0x02)?;
+    let bank2 = sample(board, 0x4000)?;
+
+    Ok(bank1 != bank2)
+}
+
+fn select_bank(board: &mut CubicStyleBoard, mapper: Mapper, bank: usize) -> Result<()> {
+    match mapper {
+        Mapper::Mbc1 => {
+            write(board, 0x2000, (bank & 0b00011111) as u8)?;
+            write(board, 0x4000, ((bank >> 5) & 0b00000011) as u8)?;
+        }
+        Mapper::Mbc2 => {
+            write(board, 0x2100, (bank & 0b00001111) as u8)?;
+        }
+        Mapper::Mbc5 => {
+            write(board, 0x2000, (bank & 0xFF) as u8)?;
+            write(board, 0x3000, ((bank >> 8) & 0b00000001) as u8)?;
+        }
+        _ => {
+            write(board, 0x2000, (bank & 0xFF) as u8)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn sample(board: &mut CubicStyleBoard, base: u16) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(SAMPLE_COUNT as usize);
+
+    for i in 0..SAMPLE_COUNT {
+        board.set_addr(base + i * SAMPLE_STRIDE);
+        data.push(board.read_byte()?);
+    }
+
+    Ok(data)
+}
+
+fn write(board: &mut CubicStyleBoard, addr: u16, val: u8) -> Result<()> {
+    board.set_addr(addr);
+    board.write_byte(val)
+}
@@ -1,13 +1,15 @@
-use crate::mbc::RomHeaderReader;
+use crate::error::{Error, Result};
+use crate::licensee::{new_licensee, old_licensee, USE_NEW_LICENSEE_CODE};
 use crate::utils::bytes_to_hex;
-use anyhow::{bail, Context, Result};
+use log::warn;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use std::fmt;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom};
 
-#[derive(FromPrimitive, Copy, Clone, Debug)]
+#[derive(FromPrimitive, Copy, Clone, Debug, Default)]
 pub enum MbcType {
+    #[default]
     RomOnly = 0x00,
     Mbc1 = 0x01,
     Mbc1Ram = 0x02,
@@ -31,35 +33,176 @@ pub enum MbcType {
     Mbc5RumbleRamBattery = 0x1E,
 }
 
-impl Default for MbcType {
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RomSize {
+    Bytes(usize),
+    Unknown(u8),
+}
+
+impl Default for RomSize {
     fn default() -> Self {
-        MbcType::RomOnly
+        RomSize::Bytes(0x8000)
     }
 }
 
-#[derive(FromPrimitive, Copy, Clone, Debug)]
-pub enum DestinationCode {
-    Japanese = 0x00,
-    NonJapanese = 0x01,
-    Unknown = 0xFF,
+impl RomSize {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            n @ 0x00..=0x08 => RomSize::Bytes((32 * 1024) << n),
+            0x52 => RomSize::Bytes((1.1 * 1024.0 * 1024.0) as usize),
+            0x53 => RomSize::Bytes((1.2 * 1024.0 * 1024.0) as usize),
+            0x54 => RomSize::Bytes((1.5 * 1024.0 * 1024.0) as usize),
+            unknown => RomSize::Unknown(unknown),
+        }
+    }
+
+    pub fn bytes(&self) -> Option<usize> {
+        match *self {
+            RomSize::Bytes(n) => Some(n),
+            RomSize::Unknown(_) => None,
+        }
+    }
+
+    pub fn code(&self) -> Option<u8> {
+        match *self {
+            RomSize::Unknown(code) => Some(code),
+            RomSize::Bytes(n) => (0x00..=0x08)
+                .chain(0x52..=0x54)
+                .find(|&code| RomSize::from_code(code) == RomSize::Bytes(n)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RamSize {
+    Bytes(usize),
+    Unknown(u8),
 }
 
-impl Default for DestinationCode {
+impl Default for RamSize {
     fn default() -> Self {
-        DestinationCode::Japanese
+        RamSize::Bytes(0)
     }
 }
 
+impl RamSize {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x00 => RamSize::Bytes(0),
+            0x01 => RamSize::Bytes(2 * 1024),
+            0x02 => RamSize::Bytes(8 * 1024),
+            0x03 => RamSize::Bytes(32 * 1024),
+            0x04 => RamSize::Bytes(128 * 1024),
+            0x05 => RamSize::Bytes(64 * 1024),
+            unknown => RamSize::Unknown(unknown),
+        }
+    }
+
+    pub fn bytes(&self) -> Option<usize> {
+        match *self {
+            RamSize::Bytes(n) => Some(n),
+            RamSize::Unknown(_) => None,
+        }
+    }
+
+    pub fn code(&self) -> Option<u8> {
+        match *self {
+            RamSize::Unknown(code) => Some(code),
+            RamSize::Bytes(n) => {
+                (0x00..=0x05).find(|&code| RamSize::from_code(code) == RamSize::Bytes(n))
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum SgbFlag {
+    #[default]
+    Unsupported,
+    Supported,
+    Unknown(u8),
+}
+
+impl SgbFlag {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x00 => SgbFlag::Unsupported,
+            0x03 => SgbFlag::Supported,
+            unknown => SgbFlag::Unknown(unknown),
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match *self {
+            SgbFlag::Unsupported => 0x00,
+            SgbFlag::Supported => 0x03,
+            SgbFlag::Unknown(code) => code,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum DestinationCode {
+    #[default]
+    Japanese,
+    NonJapanese,
+    Unknown(u8),
+}
+
+impl DestinationCode {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x00 => DestinationCode::Japanese,
+            0x01 => DestinationCode::NonJapanese,
+            unknown => DestinationCode::Unknown(unknown),
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match *self {
+            DestinationCode::Japanese => 0x00,
+            DestinationCode::NonJapanese => 0x01,
+            DestinationCode::Unknown(code) => code,
+        }
+    }
+}
+
+pub const HEADER_SIZE: usize = 0x0050;
+
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum CgbSupport {
+    #[default]
+    None,
+    Compatible,
+    Only,
+}
+
+pub const NINTENDO_LOGO: [u8; 0x0030] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Copy, Clone, Debug)]
+pub struct LogoMismatch {
+    pub addr: u16,
+    pub expected: u8,
+    pub actual: u8,
+}
+
 #[derive(Copy, Clone)]
 pub struct RomHeader {
     pub entry_point: [u8; 4],
     pub logo: [u8; 0x0030],
-    pub title: [u8; 0x0010],
+    pub raw_title: [u8; 0x0010],
+    pub manufacturer_code: Option<[u8; 4]>,
+    pub cgb_support: CgbSupport,
     pub new_licensee_code: [u8; 2],
-    pub sgb_flag: bool,
+    pub sgb_flag: SgbFlag,
     pub mbc_type: MbcType,
-    pub rom_size: usize,
-    pub ram_size: usize,
+    pub raw_mbc_type: u8,
+    pub rom_size: RomSize,
+    pub ram_size: RamSize,
     pub destination_code: DestinationCode,
     pub old_licensee_code: u8,
     pub mask_rom_version_number: u8,
@@ -72,10 +215,13 @@ impl Default for RomHeader {
         Self {
             entry_point: Default::default(),
             logo: [0; 0x0030],
-            title: Default::default(),
+            raw_title: Default::default(),
+            manufacturer_code: Default::default(),
+            cgb_support: Default::default(),
             new_licensee_code: Default::default(),
             sgb_flag: Default::default(),
             mbc_type: Default::default(),
+            raw_mbc_type: Default::default(),
             rom_size: Default::default(),
             ram_size: Default::default(),
             destination_code: Default::default(),
@@ -92,10 +238,16 @@ impl fmt::Debug for RomHeader {
         f.debug_struct("Rom")
             .field("entry_point", &bytes_to_hex(&self.entry_point[..]))
             .field("logo", &bytes_to_hex(&self.logo[..]))
-            .field("title", &bytes_to_hex(&self.title[..]))
+            .field("raw_title", &bytes_to_hex(&self.raw_title[..]))
+            .field(
+                "manufacturer_code",
+                &self.manufacturer_code.map(|code| bytes_to_hex(&code)),
+            )
+            .field("cgb_support", &self.cgb_support)
             .field("new_licensee_code", &bytes_to_hex(&self.new_licensee_code))
             .field("sgb_flag", &self.sgb_flag)
             .field("mbc_type", &self.mbc_type)
+            .field("raw_mbc_type", &self.raw_mbc_type)
             .field("rom_size", &self.rom_size)
             .field("ram_size", &self.ram_size)
             .field("destination_code", &self.destination_code)
@@ -108,7 +260,175 @@ impl fmt::Debug for RomHeader {
 }
 
 impl RomHeader {
-    pub fn from_reader(reader: &mut RomHeaderReader) -> Result<Self> {
+    // MBC30はヘッダ上MBC3と区別できないため、MBC3の上限(2MiB ROM / 32KiB RAM)を
+    // 超えるサイズが宣言されているかで判定する
+    pub fn is_mbc30(&self) -> bool {
+        match self.mbc_type {
+            MbcType::Mbc3
+            | MbcType::Mbc3Ram
+            | MbcType::Mbc3RamBattery
+            | MbcType::Mbc3TimerRamBattery => {
+                self.rom_size.bytes().unwrap_or_default() > 2 * 1024 * 1024
+                    || self.ram_size.bytes().unwrap_or_default() > 32 * 1024
+            }
+            _ => false,
+        }
+    }
+
+    pub fn logo_mismatches(&self) -> Vec<LogoMismatch> {
+        self.logo
+            .iter()
+            .zip(NINTENDO_LOGO.iter())
+            .enumerate()
+            .filter(|(_, (actual, expected))| actual != expected)
+            .map(|(i, (&actual, &expected))| LogoMismatch {
+                addr: 0x0104 + i as u16,
+                expected,
+                actual,
+            })
+            .collect()
+    }
+
+    pub fn title(&self) -> String {
+        let len = self.title_len();
+
+        let title = &self.raw_title[..len];
+        let end = title.iter().position(|&b| b == 0x00).unwrap_or(len);
+
+        String::from_utf8_lossy(&title[..end])
+            .trim_end()
+            .to_string()
+    }
+
+    pub fn manufacturer_code(&self) -> Option<String> {
+        self.manufacturer_code
+            .map(|code| String::from_utf8_lossy(&code).to_string())
+    }
+
+    pub fn publisher(&self) -> Option<&'static str> {
+        if self.old_licensee_code == USE_NEW_LICENSEE_CODE {
+            new_licensee(self.new_licensee_code)
+        } else {
+            old_licensee(self.old_licensee_code)
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self.cgb_support {
+            CgbSupport::None => "gb",
+            CgbSupport::Compatible | CgbSupport::Only => "gbc",
+        }
+    }
+
+    fn title_len(&self) -> usize {
+        if self.manufacturer_code.is_some() {
+            0x0B
+        } else if self.cgb_support != CgbSupport::None {
+            0x0F
+        } else {
+            0x10
+        }
+    }
+
+    pub fn set_title(&mut self, title: &str) -> Result<()> {
+        let len = self.title_len();
+
+        if !title.is_ascii() || title.len() > len {
+            return Err(Error::InvalidTitle(len));
+        }
+
+        self.raw_title[..len].iter_mut().for_each(|b| *b = 0x00);
+        self.raw_title[..title.len()].copy_from_slice(title.as_bytes());
+
+        Ok(())
+    }
+
+    pub fn set_cartridge_type(&mut self, code: u8) {
+        self.raw_mbc_type = code;
+        self.mbc_type = MbcType::from_u8(code).unwrap_or_default();
+    }
+
+    // 0100-014Fのヘッダ領域をバイト列に変換する
+    // ヘッダチェックサムは格納されている値をそのまま書き出すため、必要に応じてfix_checksumsで再計算する
+    pub fn to_bytes(&self) -> Result<[u8; HEADER_SIZE]> {
+        let mut bytes = [0; HEADER_SIZE];
+
+        bytes[0x00..0x04].copy_from_slice(&self.entry_point);
+        bytes[0x04..0x34].copy_from_slice(&self.logo);
+        bytes[0x34..0x44].copy_from_slice(&self.raw_title);
+
+        if let Some(code) = self.manufacturer_code {
+            bytes[0x3F..0x43].copy_from_slice(&code);
+        }
+
+        match self.cgb_support {
+            CgbSupport::None => {}
+            CgbSupport::Compatible => bytes[0x43] = 0x80,
+            CgbSupport::Only => bytes[0x43] = 0xC0,
+        }
+
+        bytes[0x44..0x46].copy_from_slice(&self.new_licensee_code);
+        bytes[0x46] = self.sgb_flag.code();
+
+        // 不明なカートリッジタイプは元の値をそのまま書き出す
+        bytes[0x47] = match MbcType::from_u8(self.raw_mbc_type) {
+            Some(_) => self.mbc_type as u8,
+            None => self.raw_mbc_type,
+        };
+
+        bytes[0x48] = self
+            .rom_size
+            .code()
+            .ok_or_else(|| Error::UnencodableRomSize(self.rom_size.bytes().unwrap_or_default()))?;
+        bytes[0x49] = self
+            .ram_size
+            .code()
+            .ok_or_else(|| Error::UnencodableRamSize(self.ram_size.bytes().unwrap_or_default()))?;
+        bytes[0x4A] = self.destination_code.code();
+        bytes[0x4B] = self.old_licensee_code;
+        bytes[0x4C] = self.mask_rom_version_number;
+        bytes[0x4D] = self.header_checksum;
+        bytes[0x4E..0x50].copy_from_slice(&self.global_checksum);
+
+        Ok(bytes)
+    }
+
+    pub fn expected_global_checksum(&self) -> u16 {
+        u16::from_be_bytes(self.global_checksum)
+    }
+
+    pub fn is_logo_valid(&self) -> bool {
+        self.logo == NINTENDO_LOGO
+    }
+
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::parse(reader, true, true)
+    }
+
+    // 非正規カートリッジ向けに、MBCの種類とチェックサムの不正を許容して解析する
+    pub fn from_reader_lenient<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::parse(reader, false, false)
+    }
+
+    // 未対応のMBCの種類だけを許容して解析する。接触不良などによるチェックサムの不正はエラーにする
+    pub fn from_reader_any_mapper<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        Self::parse(reader, false, true)
+    }
+
+    // ROMファイルやヘッダ部分(0x0000-0x014F)のバイト列から解析する
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::from_reader(&mut Cursor::new(bytes))
+    }
+
+    pub fn from_bytes_lenient(bytes: &[u8]) -> Result<Self> {
+        Self::from_reader_lenient(&mut Cursor::new(bytes))
+    }
+
+    fn parse<R: Read + Seek>(
+        reader: &mut R,
+        strict_mapper: bool,
+        strict_checksum: bool,
+    ) -> Result<Self> {
         let mut rom = Self::default();
 
         // @see https://gbdev.io/pandocs/#the-cartridge-header
@@ -116,124 +436,339 @@ impl RomHeader {
         reader.seek(SeekFrom::Start(0x0100))?;
 
         // 0100-0103 - Entry Point
-        reader.read_exact(&mut rom.entry_point[..])?;
+        read_field(reader, &mut rom.entry_point, "Entry Point")?;
 
         // 0104-0133 - Nintendo Logo
-        reader.read_exact(&mut rom.logo[..])?;
+        read_field(reader, &mut rom.logo, "Nintendo Logo")?;
 
         // 0134-0143 - Title
-        reader.read_exact(&mut rom.title[..])?;
+        read_field(reader, &mut rom.raw_title, "Title")?;
+
+        // 0143 - CGB Flag
+        rom.cgb_support = match rom.raw_title[0x0F] {
+            0x80 => CgbSupport::Compatible,
+            0xC0 => CgbSupport::Only,
+            _ => CgbSupport::None,
+        };
+
+        // 013F-0142 - Manufacturer Code
+        // 有無を示すフラグはないため、CGB対応かつ英大文字と数字の4文字であれば存在するとみなす
+        let code = &rom.raw_title[0x0B..0x0F];
+
+        if rom.cgb_support != CgbSupport::None
+            && code
+                .iter()
+                .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+        {
+            let mut manufacturer_code = [0; 4];
+            manufacturer_code.copy_from_slice(code);
+
+            rom.manufacturer_code = Some(manufacturer_code);
+        }
 
         // 0144-0145 - New Licensee Code
-        reader.read_exact(&mut rom.new_licensee_code[..])?;
+        read_field(reader, &mut rom.new_licensee_code, "New Licensee Code")?;
 
         // 0146 - SGB Flag
-        rom.sgb_flag = match reader.take(1).bytes().next() {
-            Some(Ok(0x00)) => false,
-            Some(Ok(0x03)) => true,
-            Some(Ok(unknown)) => {
-                eprintln!("unknown SGB Flag {:#X}", unknown);
+        rom.sgb_flag = SgbFlag::from_code(read_u8(reader, "SGB Flag")?);
 
-                false
-            }
-            Some(Err(e)) => bail!("error occured while reading the SGB Flag {}", e),
-            None => bail!("unexpected EOF while reading the SGB Flag"),
-        };
+        if let SgbFlag::Unknown(unknown) = rom.sgb_flag {
+            warn!("unknown SGB Flag {:#X}", unknown);
+        }
 
         // 0147 - Cartridge Type
-        if let Some(Ok(typ)) = reader.take(1).bytes().next() {
-            rom.mbc_type = FromPrimitive::from_u8(typ).context("unknown mbc type")?;
-        } else {
-            bail!("failed to parse the Cardridge Type");
+        rom.raw_mbc_type = read_u8(reader, "Cartridge Type")?;
+
+        match FromPrimitive::from_u8(rom.raw_mbc_type) {
+            Some(mbc_type) => rom.mbc_type = mbc_type,
+            None if strict_mapper => return Err(Error::UnsupportedMapper(rom.raw_mbc_type)),
+            None => warn!("unknown mbc type {:#X}", rom.raw_mbc_type),
         }
 
         // 0148 - ROM Size
-        rom.rom_size = match reader.take(1).bytes().next() {
-            Some(Ok(n @ 0x00..=0x08)) => ((32 * 1024) << n) as usize,
-            Some(Ok(0x52)) => (1.1 * 1024.0 * 1024.0) as usize,
-            Some(Ok(0x53)) => (1.2 * 1024.0 * 1024.0) as usize,
-            Some(Ok(0x54)) => (1.5 * 1024.0 * 1024.0) as usize,
-            Some(Ok(unknown)) => {
-                eprintln!("unknown ROM Size {:#X}", unknown);
-
-                0
-            }
-            Some(Err(e)) => bail!("error occured while reading the ROM Size {}", e),
-            None => bail!("unexpected EOF while reading the ROM Size"),
-        };
+        rom.rom_size = RomSize::from_code(read_u8(reader, "ROM Size")?);
+
+        if let RomSize::Unknown(unknown) = rom.rom_size {
+            warn!("unknown ROM Size {:#X}", unknown);
+        }
 
         // 0149 - RAM Size
-        rom.ram_size = match reader.take(1).bytes().next() {
-            Some(Ok(0x00)) => 0_usize,
-            Some(Ok(0x01)) => 2 * 1024 * 1024_usize,
-            Some(Ok(0x02)) => 8 * 1024 * 1024_usize,
-            Some(Ok(0x03)) => 32 * 1024 * 1024_usize,
-            Some(Ok(0x04)) => 128 * 1024 * 1024_usize,
-            Some(Ok(0x05)) => 64 * 1024 * 1024_usize,
-            Some(Ok(unknown)) => {
-                eprintln!("unknown RAM Size {:#X}", unknown);
-
-                0
-            }
-            Some(Err(e)) => bail!("error occured while reading the RAM Size {}", e),
-            None => bail!("unexpected EOF while reading the RAM Size"),
-        };
+        rom.ram_size = RamSize::from_code(read_u8(reader, "RAM Size")?);
+
+        if let RamSize::Unknown(unknown) = rom.ram_size {
+            warn!("unknown RAM Size {:#X}", unknown);
+        }
 
         // 014A - Destination Code
-        if let Some(Ok(code)) = reader.take(1).bytes().next() {
-            if let Some(destination_code) = FromPrimitive::from_u8(code) {
-                rom.destination_code = destination_code;
-            } else {
-                rom.destination_code = DestinationCode::Unknown;
-            }
-        } else {
-            bail!("failed to parse the Destination Code");
+        rom.destination_code = DestinationCode::from_code(read_u8(reader, "Destination Code")?);
+
+        if let DestinationCode::Unknown(unknown) = rom.destination_code {
+            warn!("unknown Destination Code {:#X}", unknown);
         }
 
         // 014B - Old Licensee Code
-        rom.old_licensee_code = reader
-            .take(1)
-            .bytes()
-            .next()
-            .context("failed to parse the Old Licensee Code")??;
+        rom.old_licensee_code = read_u8(reader, "Old Licensee Code")?;
 
         // 014C - Mask ROM Version number
-        rom.mask_rom_version_number = reader
-            .take(1)
-            .bytes()
-            .next()
-            .context("failed to parse the Mask ROM Version number")??;
+        rom.mask_rom_version_number = read_u8(reader, "Mask ROM Version number")?;
 
         // 014D - Header Checksum
-        rom.header_checksum = reader
-            .take(1)
-            .bytes()
-            .next()
-            .context("failed to parse the Header Checksum")??;
+        rom.header_checksum = read_u8(reader, "Header Checksum")?;
 
         // 014E-014F - Global Checksum
-        reader.read_exact(&mut rom.global_checksum[..])?;
+        read_field(reader, &mut rom.global_checksum, "Global Checksum")?;
 
         reader.seek(SeekFrom::Start(0x0134))?;
 
         let mut chksum: u8 = 0;
 
         for _ in 0x0134..=0x014C {
-            if let Some(Ok(b)) = reader.take(1).bytes().next() {
-                chksum = chksum.wrapping_sub(b).wrapping_sub(1);
-            } else {
-                bail!("error occured while checking header chksum");
-            }
+            let b = read_u8(reader, "Header Checksum")?;
+
+            chksum = chksum.wrapping_sub(b).wrapping_sub(1);
         }
 
         if rom.header_checksum != chksum {
-            bail!(
-                "invalid checksum expected: {}, actual: {}",
-                rom.header_checksum,
-                chksum
-            );
+            let e = Error::HeaderChecksum {
+                expected: rom.header_checksum,
+                actual: chksum,
+            };
+
+            if strict_checksum {
+                return Err(e);
+            }
+
+            warn!("{}", e);
         }
 
         Ok(rom)
     }
 }
+
+// 014E-014F自身を除いたROM全体の16bitの和
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GlobalChecksum {
+    sum: u16,
+    offset: usize,
+}
+
+impl GlobalChecksum {
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            if self.offset != 0x014E && self.offset != 0x014F {
+                self.sum = self.sum.wrapping_add(b as u16);
+            }
+
+            self.offset += 1;
+        }
+    }
+
+    pub fn value(&self) -> u16 {
+        self.sum
+    }
+}
+
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x0134..=0x014C]
+        .iter()
+        .fold(0_u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1))
+}
+
+// ヘッダチェックサムとグローバルチェックサムを再計算してROMに書き込む
+pub fn fix_checksums(rom: &mut [u8]) -> Result<(u8, u16)> {
+    if rom.len() < 0x0150 {
+        return Err(Error::RomTooSmall(rom.len()));
+    }
+
+    let header = header_checksum(rom);
+    rom[0x014D] = header;
+
+    let global = global_checksum(rom);
+    rom[0x014E..0x0150].copy_from_slice(&global.to_be_bytes());
+
+    Ok((header, global))
+}
+
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    let mut checksum = GlobalChecksum::default();
+
+    checksum.update(rom);
+    checksum.value()
+}
+
+// ボードのエラーはそのまま返し、ファイルが短い場合などの入出力エラーにだけフィールド名を付ける
+fn read_field<R: Read>(reader: &mut R, buf: &mut [u8], field: &'static str) -> Result<()> {
+    reader.read_exact(buf).map_err(|e| match Error::from(e) {
+        Error::Io(source) => Error::HeaderField { field, source },
+        e => e,
+    })
+}
+
+fn read_u8<R: Read>(reader: &mut R, field: &'static str) -> Result<u8> {
+    let mut buf = [0; 1];
+
+    read_field(reader, &mut buf, field)?;
+
+    Ok(buf[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    // 0x0000-0x014Fのヘッダ領域だけを持つROMを作る
+    fn fixture(title: &[u8], cartridge_type: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x0150];
+
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x0134..0x0134 + title.len()].copy_from_slice(title);
+        rom[0x0147] = cartridge_type;
+        rom[0x0148] = 0x01;
+        rom[0x0149] = 0x00;
+        rom[0x014A] = 0x01;
+        rom[0x014B] = 0x01;
+        rom[0x014C] = 0x01;
+        rom[0x014D] = header_checksum(&rom);
+
+        rom
+    }
+
+    #[test]
+    fn from_bytes_parses_valid_header() {
+        let header = RomHeader::from_bytes(&fixture(b"TETRIS", 0x01)).unwrap();
+
+        assert_eq!(header.title(), "TETRIS");
+        assert!(header.is_logo_valid());
+        assert!(matches!(header.mbc_type, MbcType::Mbc1));
+        assert_eq!(header.raw_mbc_type, 0x01);
+        assert_eq!(header.rom_size, RomSize::Bytes(0x10000));
+        assert_eq!(header.ram_size, RamSize::Bytes(0));
+        assert_eq!(header.destination_code, DestinationCode::NonJapanese);
+        assert_eq!(header.cgb_support, CgbSupport::None);
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.mask_rom_version_number, 0x01);
+    }
+
+    #[test]
+    fn bad_header_checksum_is_rejected_only_by_strict_parser() {
+        let mut rom = fixture(b"TETRIS", 0x01);
+        rom[0x014D] ^= 0xFF;
+
+        assert!(matches!(
+            RomHeader::from_bytes(&rom),
+            Err(Error::HeaderChecksum { .. })
+        ));
+        assert_eq!(
+            RomHeader::from_bytes_lenient(&rom).unwrap().title(),
+            "TETRIS"
+        );
+    }
+
+    #[test]
+    fn unknown_codes_keep_raw_values() {
+        let mut rom = fixture(b"TETRIS", 0x01);
+        rom[0x0148] = 0x20;
+        rom[0x0149] = 0x09;
+        rom[0x014A] = 0x05;
+        rom[0x014D] = header_checksum(&rom);
+
+        let header = RomHeader::from_bytes(&rom).unwrap();
+
+        assert_eq!(header.rom_size, RomSize::Unknown(0x20));
+        assert_eq!(header.ram_size, RamSize::Unknown(0x09));
+        assert_eq!(header.destination_code, DestinationCode::Unknown(0x05));
+    }
+
+    #[test]
+    fn unknown_cartridge_type_is_rejected_only_by_strict_parser() {
+        let rom = fixture(b"TETRIS", 0x50);
+
+        assert!(matches!(
+            RomHeader::from_bytes(&rom),
+            Err(Error::UnsupportedMapper(0x50))
+        ));
+        assert_eq!(
+            RomHeader::from_bytes_lenient(&rom).unwrap().raw_mbc_type,
+            0x50
+        );
+    }
+
+    #[test]
+    fn short_file_reports_field() {
+        let rom = fixture(b"TETRIS", 0x01);
+
+        assert!(matches!(
+            RomHeader::from_bytes(&rom[..0x0140]),
+            Err(Error::HeaderField { field: "Title", .. })
+        ));
+        assert!(matches!(
+            RomHeader::from_bytes(&rom[..0x0147]),
+            Err(Error::HeaderField {
+                field: "Cartridge Type",
+                ..
+            })
+        ));
+    }
+
+    // 0x0147以降の読み出しでボードのエラーを返すリーダー
+    struct FailingReader(Cursor<Vec<u8>>);
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.position() >= 0x0147 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, Error::BankMirror));
+            }
+
+            self.0.read(buf)
+        }
+    }
+
+    impl Seek for FailingReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    #[test]
+    fn board_errors_are_not_wrapped() {
+        let mut reader = FailingReader(Cursor::new(fixture(b"TETRIS", 0x01)));
+
+        assert!(matches!(
+            RomHeader::from_reader(&mut reader),
+            Err(Error::BankMirror)
+        ));
+    }
+
+    #[test]
+    fn to_bytes_round_trips() {
+        let rom = fixture(b"TETRIS", 0x01);
+        let header = RomHeader::from_bytes(&rom).unwrap();
+
+        assert_eq!(&header.to_bytes().unwrap()[..], &rom[0x0100..0x0150]);
+    }
+
+    #[test]
+    fn to_bytes_round_trips_manufacturer_code_and_cgb_flag() {
+        let mut rom = fixture(b"POKEMON", 0x1B);
+        rom[0x013F..0x0143].copy_from_slice(b"AAXJ");
+        rom[0x0143] = 0xC0;
+        rom[0x014D] = header_checksum(&rom);
+
+        let header = RomHeader::from_bytes(&rom).unwrap();
+
+        assert_eq!(header.manufacturer_code(), Some("AAXJ".to_string()));
+        assert_eq!(header.cgb_support, CgbSupport::Only);
+        assert_eq!(header.title(), "POKEMON");
+        assert_eq!(&header.to_bytes().unwrap()[..], &rom[0x0100..0x0150]);
+    }
+
+    #[test]
+    fn to_bytes_round_trips_unknown_cartridge_type() {
+        let rom = fixture(b"TETRIS", 0x50);
+        let header = RomHeader::from_bytes_lenient(&rom).unwrap();
+
+        assert_eq!(&header.to_bytes().unwrap()[..], &rom[0x0100..0x0150]);
+    }
+}
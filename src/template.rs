@@ -0,0 +1,213 @@
+use crate::error::{Error, Result};
+use crate::hash::Hashes;
+use crate::rom::RomHeader;
+use std::str::FromStr;
+
+// 読み出しが終わるまでハッシュは分からないため、それまではこの文字列で埋める
+const PENDING: &str = "pending";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Field {
+    Title,
+    Code,
+    Version,
+    Ext,
+    Crc32,
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl Field {
+    fn is_hash(self) -> bool {
+        matches!(
+            self,
+            Field::Crc32 | Field::Md5 | Field::Sha1 | Field::Sha256
+        )
+    }
+}
+
+impl FromStr for Field {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "title" => Field::Title,
+            "code" => Field::Code,
+            "version" => Field::Version,
+            "ext" => Field::Ext,
+            "crc32" => Field::Crc32,
+            "md5" => Field::Md5,
+            "sha1" => Field::Sha1,
+            "sha256" => Field::Sha256,
+            _ => return Err(Error::InvalidTemplate(format!("unknown field {{{}}}", s))),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+// {title}_{version}_{crc32}.{ext} のような出力ファイル名のテンプレート
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileNameTemplate {
+    parts: Vec<Part>,
+}
+
+impl FileNameTemplate {
+    // 置き換える項目を含まない、そのままのファイル名かどうか
+    pub fn is_literal(&self) -> bool {
+        self.parts
+            .iter()
+            .all(|part| matches!(part, Part::Literal(_)))
+    }
+
+    pub fn needs_hashes(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Field(field) if field.is_hash()))
+    }
+
+    pub fn render(&self, header: &RomHeader, hashes: Option<&Hashes>) -> String {
+        let mut name = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => name += s,
+                Part::Field(field) => name += &Self::field(*field, header, hashes),
+            }
+        }
+
+        name
+    }
+
+    fn field(field: Field, header: &RomHeader, hashes: Option<&Hashes>) -> String {
+        let hash = |f: fn(&Hashes) -> String| hashes.map(f).unwrap_or_else(|| PENDING.to_string());
+
+        match field {
+            Field::Title => {
+                let title = sanitize(&header.title());
+
+                if title.is_empty() {
+                    "untitled".to_string()
+                } else {
+                    title
+                }
+            }
+            Field::Code => sanitize(&header.manufacturer_code().unwrap_or_default()),
+            Field::Version => header.mask_rom_version_number.to_string(),
+            Field::Ext => header.extension().to_string(),
+            Field::Crc32 => hash(Hashes::crc32_hex),
+            Field::Md5 => hash(Hashes::md5_hex),
+            Field::Sha1 => hash(Hashes::sha1_hex),
+            Field::Sha256 => hash(Hashes::sha256_hex),
+        }
+    }
+}
+
+impl Default for FileNameTemplate {
+    fn default() -> Self {
+        Self {
+            parts: vec![
+                Part::Field(Field::Title),
+                Part::Literal(".".to_string()),
+                Part::Field(Field::Ext),
+            ],
+        }
+    }
+}
+
+impl FromStr for FileNameTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| Error::InvalidTemplate(format!("unclosed {{ in {}", s)))?;
+
+            parts.push(Part::Field(rest[start + 1..start + end].parse()?));
+
+            rest = &rest[start + end + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+// ファイル名に使えない文字を_に置き換える
+fn sanitize(s: &str) -> String {
+    s.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> RomHeader {
+        let mut header = RomHeader::default();
+
+        header.set_title("POKEMON RED").unwrap();
+        header.mask_rom_version_number = 1;
+
+        header
+    }
+
+    #[test]
+    fn render_fills_header_fields() {
+        let template = "{title}_{version}.{ext}"
+            .parse::<FileNameTemplate>()
+            .unwrap();
+
+        assert!(!template.is_literal());
+        assert!(!template.needs_hashes());
+        assert_eq!(template.render(&header(), None), "POKEMON_RED_1.gb");
+    }
+
+    #[test]
+    fn render_uses_placeholder_until_hashes_are_known() {
+        let template = "{title}_{crc32}.{ext}".parse::<FileNameTemplate>().unwrap();
+        let hashes = Hashes::of(b"abc");
+
+        assert!(template.needs_hashes());
+        assert_eq!(template.render(&header(), None), "POKEMON_RED_pending.gb");
+        assert_eq!(
+            template.render(&header(), Some(&hashes)),
+            "POKEMON_RED_352441C2.gb"
+        );
+    }
+
+    #[test]
+    fn literal_name_is_kept() {
+        let template = "roms/tetris.gb".parse::<FileNameTemplate>().unwrap();
+
+        assert!(template.is_literal());
+        assert_eq!(template.render(&header(), None), "roms/tetris.gb");
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        assert!(matches!(
+            "{foo}.gb".parse::<FileNameTemplate>(),
+            Err(Error::InvalidTemplate(_))
+        ));
+        assert!(matches!(
+            "{title.gb".parse::<FileNameTemplate>(),
+            Err(Error::InvalidTemplate(_))
+        ));
+    }
+}